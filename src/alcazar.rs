@@ -1,15 +1,30 @@
-use crate::error::Result;
+use crate::error::{AlcazarError, Result};
+use crate::extensions::AppState;
+use crate::middleware::{Middleware, Next};
 use crate::request::HttpRequest;
+use crate::response::IntoResponse;
 use crate::router::Router;
+use crate::routing::endpoint::BoxHandler;
+use crate::status_code::StatusCode;
+use crate::upgrade;
 use bastion_executor::run::run;
+use futures::future::FutureExt;
 use lightproc::prelude::ProcStack;
 use std::io::Write;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use tracing::info;
 
+// Number of connections handled at the same time by default.
+const MAX_CONNECTIONS: usize = 1024;
+
 pub struct AppBuilder {
     addr: SocketAddr,
     router: Router,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    state: AppState,
+    max_connections: usize,
 }
 
 impl Default for AppBuilder {
@@ -17,6 +32,9 @@ impl Default for AppBuilder {
         Self {
             addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0),
             router: Router::default(),
+            middlewares: Vec::new(),
+            state: AppState::new(),
+            max_connections: MAX_CONNECTIONS,
         }
     }
 }
@@ -32,26 +50,64 @@ impl AppBuilder {
         self
     }
 
+    // Adds a middleware running for every request, before the router's ones.
+    pub fn with_middleware<M: Middleware>(&mut self, middleware: M) -> &mut Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    // Registers a value shared by all the requests, replacing the previous one of the
+    // same type. Handlers get it with the `State<T>` extractor, and middleware with
+    // `HttpRequest::state`.
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) -> &mut Self {
+        self.state.insert(state);
+        self
+    }
+
+    // Sets how many connections are handled at the same time, each one having its own
+    // thread. Clients connecting beyond that are answered with 503 Service Unavailable.
+    pub fn set_max_connections(&mut self, max_connections: usize) -> &mut Self {
+        self.max_connections = max_connections;
+        self
+    }
+
     pub fn start(&self) -> Result<App> {
         let listener = TcpListener::bind(self.addr)?;
         let local_addr = listener.local_addr()?;
-        let router = self.router.clone();
+        let router = Arc::new(self.router.clone());
+        let dispatch: BoxHandler = Arc::new(move |request| {
+            let router = router.clone();
+            async move { router.dispatch(request).await }.boxed()
+        });
+        let pipeline = Next::new(self.middlewares.clone(), dispatch);
+        let state = Arc::new(self.state.clone());
+        let max_connections = self.max_connections;
+        let active = Arc::new(AtomicUsize::new(0));
 
         info!("listening to {}", local_addr);
-        std::thread::spawn(move || -> Result<()> {
-            loop {
-                match listener.accept() {
-                    Ok((mut stream, _addr)) => {
-                        let request = HttpRequest::parse_stream(&stream)?;
-                        let endpoint = router.get_endpoint(request.method(), request.path())?;
-                        // TODO: Call the endpoint's handler and write the response back
-                        let handler = run(async { endpoint.handler().await }, ProcStack::default());
-
-                        stream.write_all(handler.into_bytes_response().as_slice())?;
-                        stream.flush()?;
-                    }
-                    Err(_) => info!("Client connection failed."),
+        std::thread::spawn(move || loop {
+            match listener.accept() {
+                Ok((stream, _addr)) => {
+                    let slot = match ConnectionSlot::acquire(&active, max_connections) {
+                        Some(slot) => slot,
+                        None => {
+                            info!("Too many connections, the client is turned away.");
+                            reject_connection(stream);
+                            continue;
+                        }
+                    };
+                    // Every connection has its own thread, so streamed responses
+                    // don't hold the other clients back
+                    let pipeline = pipeline.clone();
+                    let state = state.clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) = handle_connection(stream, pipeline, state) {
+                            info!("Connection failed: {}", err);
+                        }
+                        drop(slot);
+                    });
                 }
+                Err(_) => info!("Client connection failed."),
             }
         });
 
@@ -59,6 +115,72 @@ impl AppBuilder {
     }
 }
 
+// Place taken by a connection among the ones handled at the same time, given back
+// once dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn acquire(active: &Arc<AtomicUsize>, max_connections: usize) -> Option<Self> {
+        active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < max_connections).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| ConnectionSlot(active.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// Answers the connections beyond the limit without reading their request.
+fn reject_connection(mut stream: TcpStream) {
+    let response = (StatusCode::ServiceUnavailable, "too many connections")
+        .into_response()
+        .with_header("Connection", "close");
+    let _ = stream.write_all(&response.into_bytes_response());
+}
+
+fn handle_connection(mut stream: TcpStream, pipeline: Next, state: Arc<AppState>) -> Result<()> {
+    let mut request = match HttpRequest::parse_stream(&stream) {
+        Ok(request) => request,
+        // Malformed requests are answered before closing the connection, as the
+        // rest of what the client sent can't be framed
+        Err(AlcazarError::ParseError(err)) => {
+            let response = (StatusCode::BadRequest, err.to_string())
+                .into_response()
+                .with_header("Connection", "close");
+            let _ = stream.write_all(&response.into_bytes_response());
+            return Err(err.into());
+        }
+        Err(err) => return Err(err),
+    };
+    request.set_state(state);
+    let version = request.version();
+    let method = request.method().clone();
+    run(
+        async move {
+            let mut response = pipeline.run(request).await;
+            let upgrade = response
+                .take_upgrade()
+                .filter(|_| upgrade::accepts_upgrade(&response, &method));
+            match upgrade {
+                Some(upgrade) => {
+                    response.write_head_to(&mut stream)?;
+                    upgrade.run().await;
+                    Ok(())
+                }
+                None => response.write_to(&mut stream, version).await,
+            }
+        },
+        ProcStack::default(),
+    )?;
+    Ok(())
+}
+
 pub struct App {
     local_addr: SocketAddr,
 }
@@ -72,8 +194,10 @@ impl App {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::response::HttpResponse;
     use crate::status_code::StatusCode;
-    use std::io::{BufRead, BufReader};
+    use futures::stream;
+    use std::io::{BufRead, BufReader, Read, Write};
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
 
     fn get_ipv4_socket_addr() -> SocketAddr {
@@ -143,6 +267,150 @@ mod tests {
         assert_eq!(buffer, "HTTP/1.1 200 OK\r\n");
     }
 
+    #[test]
+    fn add_global_middleware() {
+        let router = Router::new().with_endpoint("/", &["get"], handler);
+        let alcazar = AppBuilder::default()
+            .set_addr(get_ipv4_socket_addr())
+            .set_router(router)
+            .with_middleware(|request, next: Next| async move {
+                let mut response = next.run(request).await;
+                response.headers_mut().insert("Server", "alcazar");
+                response
+            })
+            .start()
+            .expect("unwrap appbuilder");
+
+        let mut stream = TcpStream::connect(alcazar.local_addr()).expect("unwrap connect");
+        stream
+            .write_all(b"GET /missing HTTP/1.1\r\n\r\n")
+            .expect("unwrap write_all test");
+        stream.flush().expect("unwrap flush test");
+
+        let mut reader = BufReader::new(&stream);
+        let mut status_line = String::new();
+        let mut header = String::new();
+        reader
+            .read_line(&mut status_line)
+            .expect("unwrap read_line test");
+        reader
+            .read_line(&mut header)
+            .expect("unwrap read_line test");
+
+        assert_eq!(status_line, "HTTP/1.1 404 Not Found\r\n");
+        assert_eq!(header, "Server: alcazar\r\n");
+    }
+
+    #[test]
+    fn add_state() {
+        struct ServerName(&'static str);
+
+        let router = Router::new().with_endpoint("/", &["get"], handler);
+        let alcazar = AppBuilder::default()
+            .set_addr(get_ipv4_socket_addr())
+            .set_router(router)
+            .with_state(ServerName("alcazar"))
+            .with_middleware(|request: HttpRequest, next: Next| async move {
+                let name = request.state::<ServerName>().map(|name| name.0);
+                let mut response = next.run(request).await;
+                response
+                    .headers_mut()
+                    .insert("Server", name.unwrap_or("unknown"));
+                response
+            })
+            .start()
+            .expect("unwrap appbuilder");
+
+        let mut stream = TcpStream::connect(alcazar.local_addr()).expect("unwrap connect");
+        stream
+            .write_all(b"GET / HTTP/1.1\r\n\r\n")
+            .expect("unwrap write_all test");
+        stream.flush().expect("unwrap flush test");
+
+        let mut reader = BufReader::new(&stream);
+        let mut status_line = String::new();
+        let mut header = String::new();
+        reader
+            .read_line(&mut status_line)
+            .expect("unwrap read_line test");
+        reader
+            .read_line(&mut header)
+            .expect("unwrap read_line test");
+
+        assert_eq!(status_line, "HTTP/1.1 200 OK\r\n");
+        assert_eq!(header, "Server: alcazar\r\n");
+    }
+
+    async fn export() -> HttpResponse {
+        let rows = (1..=3).map(|row| Ok::<_, std::io::Error>(format!("row {}\n", row)));
+        HttpResponse::new(StatusCode::Ok).with_stream(stream::iter(rows))
+    }
+
+    fn request_export(request: &[u8]) -> String {
+        let router = Router::new().with_endpoint("/export", &["get"], export);
+        let alcazar = AppBuilder::default()
+            .set_addr(get_ipv4_socket_addr())
+            .set_router(router)
+            .start()
+            .expect("unwrap appbuilder");
+
+        let mut stream = TcpStream::connect(alcazar.local_addr()).expect("unwrap connect");
+        stream.write_all(request).expect("unwrap write_all test");
+        stream.flush().expect("unwrap flush test");
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .expect("unwrap read_to_string test");
+        response
+    }
+
+    #[test]
+    fn stream_response() {
+        let response = request_export(b"GET /export HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             6\r\nrow 1\n\r\n6\r\nrow 2\n\r\n6\r\nrow 3\n\r\n0\r\n\r\n"
+        );
+
+        let response = request_export(b"GET /export HTTP/1.0\r\n\r\n");
+        assert_eq!(
+            response,
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nrow 1\nrow 2\nrow 3\n"
+        );
+    }
+
+    #[test]
+    fn reject_connections_over_limit() {
+        let router = Router::new().with_endpoint("/", &["get"], handler);
+        let alcazar = AppBuilder::default()
+            .set_addr(get_ipv4_socket_addr())
+            .set_router(router)
+            .set_max_connections(1)
+            .start()
+            .expect("unwrap appbuilder");
+
+        // The first connection is handled while its request is still to come
+        let mut first = TcpStream::connect(alcazar.local_addr()).expect("unwrap connect");
+        let mut second = TcpStream::connect(alcazar.local_addr()).expect("unwrap connect");
+        let mut response = String::new();
+        second
+            .read_to_string(&mut response)
+            .expect("unwrap read_to_string test");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+
+        first
+            .write_all(b"GET / HTTP/1.1\r\n\r\n")
+            .expect("unwrap write_all test");
+        let mut response = String::new();
+        first
+            .read_to_string(&mut response)
+            .expect("unwrap read_to_string test");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
     #[test]
     fn try_to_connect_ipv4() {
         let router = Router::new().with_endpoint("/", &["get"], handler);
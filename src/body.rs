@@ -0,0 +1,351 @@
+use crate::error::{AlcazarError, BodyError, ParseError, Result};
+use crate::headers::Headers;
+use futures::stream::{Stream, StreamExt};
+use std::fmt;
+use std::io::{self, BufRead, Cursor, ErrorKind, Read};
+use std::pin::Pin;
+use std::result;
+use std::task::{Context, Poll};
+
+// Size of the chunks read from the body.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+// Maximum length of the chunk size and trailer lines of chunked bodies.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+enum Source {
+    // Body held in memory, which can be cloned
+    Bytes(Cursor<Vec<u8>>),
+    Reader(Box<dyn Read + Send>),
+}
+
+// Body of a request, read lazily from the connection as a stream of chunks. Reads
+// are blocking, like the rest of the connection handling, so the handler's
+// executor thread waits for the client while reading.
+pub struct Body {
+    source: Option<Source>,
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body { source: None }
+    }
+
+    pub(crate) fn from_reader<R: Read + Send + 'static>(reader: R) -> Self {
+        Body {
+            source: Some(Source::Reader(Box::new(reader))),
+        }
+    }
+
+    // Whether the rest of the body is held in memory, so it can be cloned.
+    pub fn is_buffered(&self) -> bool {
+        !matches!(self.source, Some(Source::Reader(_)))
+    }
+
+    // Clones the rest of the body, when it is held in memory.
+    pub fn try_clone(&self) -> Option<Body> {
+        let source = match &self.source {
+            None => None,
+            Some(Source::Bytes(bytes)) => Some(Source::Bytes(bytes.clone())),
+            Some(Source::Reader(_)) => return None,
+        };
+        Some(Body { source })
+    }
+
+    // Reads the rest of the body into memory, failing once it's larger than the
+    // limit. The body can then be cloned, e.g. to retry a request.
+    pub async fn buffer(&mut self, limit: usize) -> result::Result<(), BodyError> {
+        if !self.is_buffered() {
+            let bytes = std::mem::take(self).to_bytes(limit).await?;
+            *self = Body::from(bytes);
+        }
+        Ok(())
+    }
+
+    // Returns the body of the request from the connection, according to its framing.
+    // Bodies with a transfer coding must end with `chunked`, as their length couldn't
+    // be known otherwise (RFC 9112, section 6.3).
+    pub(crate) fn from_connection<R: BufRead + Send + 'static>(
+        reader: R,
+        headers: &Headers,
+    ) -> Result<Self> {
+        let coding = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|value| value.split(','))
+            .last()
+            .map(str::trim);
+        match coding {
+            Some(coding) if coding.eq_ignore_ascii_case("chunked") => {
+                return Ok(Body::from_reader(ChunkedReader::new(reader)));
+            }
+            Some(coding) => {
+                return Err(AlcazarError::ParseError(
+                    ParseError::UnsupportedTransferEncoding(coding.to_string()),
+                ))
+            }
+            None => {}
+        }
+
+        match headers.get("Content-Length") {
+            Some(length) => match length.trim().parse::<u64>() {
+                Ok(0) => Ok(Body::empty()),
+                Ok(length) => Ok(Body::from_reader(reader.take(length))),
+                Err(_) => Err(AlcazarError::ParseError(ParseError::InvalidContentLength(
+                    length.to_string(),
+                ))),
+            },
+            None => Ok(Body::empty()),
+        }
+    }
+
+    // Reads the next chunk, returning `None` once the body is complete.
+    pub(crate) fn read_chunk(&mut self) -> Option<io::Result<Vec<u8>>> {
+        let reader: &mut dyn Read = match self.source.as_mut()? {
+            Source::Bytes(bytes) => bytes,
+            Source::Reader(reader) => reader,
+        };
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => {
+                    self.source = None;
+                    return None;
+                }
+                Ok(n) => {
+                    chunk.truncate(n);
+                    return Some(Ok(chunk));
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.source = None;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+
+    // Reads the whole body, failing once it's larger than the limit.
+    pub async fn to_bytes(mut self, limit: usize) -> result::Result<Vec<u8>, BodyError> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.next().await {
+            let chunk = chunk?;
+            if bytes.len() + chunk.len() > limit {
+                return Err(BodyError::TooLarge(limit));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Body")
+            .field("consumed", &self.source.is_none())
+            .finish()
+    }
+}
+
+impl Stream for Body {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.get_mut().read_chunk())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body {
+            source: Some(Source::Bytes(Cursor::new(bytes))),
+        }
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::from(text.into_bytes())
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Body::from(text.as_bytes().to_vec())
+    }
+}
+
+// Decodes the `chunked` transfer coding, skipping the chunk extensions and trailers.
+struct ChunkedReader<R> {
+    reader: R,
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(reader: R) -> Self {
+        ChunkedReader {
+            reader,
+            remaining: 0,
+            done: false,
+        }
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        let mut reader = (&mut self.reader).take(MAX_LINE_LENGTH);
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "unexpected end of the chunked body",
+            ));
+        }
+        if !line.ends_with('\n') && reader.limit() == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "chunked body line is too long",
+            ));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn read_chunk_size(&mut self) -> io::Result<u64> {
+        let line = self.read_line()?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        u64::from_str_radix(size, 16).map_err(|_| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid chunk size {:?}", size),
+            )
+        })
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.remaining = self.read_chunk_size()?;
+            if self.remaining == 0 {
+                // Skip the trailer fields up to the final empty line
+                while !self.read_line()?.is_empty() {}
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let max = buf.len().min(self.remaining as usize);
+        let n = self.reader.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "unexpected end of the chunked body",
+            ));
+        }
+        self.remaining -= n as u64;
+        if self.remaining == 0 && !self.read_line()?.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "missing line break after the chunk",
+            ));
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::error::BodyError;
+    use crate::headers::Headers;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use std::io::Cursor;
+
+    fn body_from_connection(name: &str, value: &str, data: &'static [u8]) -> Body {
+        let mut headers = Headers::new();
+        headers.insert(name, value);
+        Body::from_connection(Cursor::new(data), &headers).unwrap()
+    }
+
+    #[test]
+    fn test_read_body_with_content_length() {
+        let body = body_from_connection("Content-Length", "5", b"helloGET / HTTP/1.1");
+
+        let bytes = run(body.to_bytes(1024), ProcStack::default()).unwrap();
+        assert_eq!(bytes, b"hello");
+    }
+
+    #[test]
+    fn test_read_chunked_body() {
+        let body = body_from_connection(
+            "Transfer-Encoding",
+            "gzip, chunked",
+            b"5;name=value\r\nhello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\n",
+        );
+
+        let bytes = run(body.to_bytes(1024), ProcStack::default()).unwrap();
+        assert_eq!(bytes, b"hello, world");
+
+        let body = body_from_connection("Transfer-Encoding", "chunked", b"5\r\nhel");
+        assert!(run(body.to_bytes(1024), ProcStack::default()).is_err());
+    }
+
+    #[test]
+    fn test_reject_long_chunk_line() {
+        let mut data = b"5;".to_vec();
+        data.extend(std::iter::repeat_n(b'x', 10_000));
+        data.extend_from_slice(b"\r\nhello\r\n0\r\n\r\n");
+        let mut headers = Headers::new();
+        headers.insert("Transfer-Encoding", "chunked");
+        let body = Body::from_connection(Cursor::new(data), &headers).unwrap();
+
+        let err = run(body.to_bytes(1024), ProcStack::default()).unwrap_err();
+        assert_eq!(err.to_string(), "chunked body line is too long");
+    }
+
+    #[test]
+    fn test_reject_unsupported_transfer_coding() {
+        let mut headers = Headers::new();
+        headers.insert("Transfer-Encoding", "chunked, gzip");
+        headers.insert("Content-Length", "5");
+        let result = Body::from_connection(Cursor::new(b"hello"), &headers);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "\"gzip\" is not a supported transfer coding"
+        );
+    }
+
+    #[test]
+    fn test_reject_body_over_limit() {
+        let body = Body::from("hello, world");
+
+        let result = run(body.to_bytes(5), ProcStack::default());
+        assert!(matches!(result, Err(BodyError::TooLarge(5))));
+    }
+
+    #[test]
+    fn test_clone_buffered_body() {
+        let mut body = body_from_connection("Content-Length", "5", b"hello");
+        assert!(!body.is_buffered());
+        assert!(body.try_clone().is_none());
+
+        run(body.buffer(1024), ProcStack::default()).unwrap();
+        let clone = body.try_clone().unwrap();
+        assert_eq!(
+            run(body.to_bytes(1024), ProcStack::default()).unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            run(clone.to_bytes(1024), ProcStack::default()).unwrap(),
+            b"hello"
+        );
+    }
+}
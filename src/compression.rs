@@ -0,0 +1,523 @@
+use crate::conditional::ETag;
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, ResponseBody};
+use crate::routing::endpoint::MethodType;
+use brotli::CompressorWriter;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::{self, StreamExt};
+use std::io::{self, Write};
+use std::mem;
+
+// Responses with a body smaller than this aren't worth compressing by default.
+const DEFAULT_MIN_SIZE: usize = 1024;
+// Quality of the brotli compression, the higher levels are too slow for responses
+// compressed on the fly.
+const BROTLI_QUALITY: u32 = 4;
+const BROTLI_WINDOW: u32 = 22;
+
+// Content types compressed, besides the `+json` and `+xml` ones. The text types are
+// all compressed except `text/event-stream`.
+const COMPRESSIBLE_TYPES: &[&str] = &[
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "application/x-ndjson",
+    "image/svg+xml",
+];
+
+// Content codings supported by the middleware, in the order of preference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+// Compressor writing into a buffer, emptied after each chunk of the body.
+enum Encoder {
+    Brotli(Box<CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+    // The `deflate` coding is the zlib format (RFC 9110, section 8.4.1.2)
+    Deflate(ZlibEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Brotli => Encoder::Brotli(Box::new(CompressorWriter::new(
+                Vec::new(),
+                4096,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            ))),
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::default())),
+            Encoding::Deflate => {
+                Encoder::Deflate(ZlibEncoder::new(Vec::new(), Compression::default()))
+            }
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Brotli(encoder) => encoder.as_mut(),
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Deflate(encoder) => encoder,
+        }
+    }
+
+    // Compresses the chunk and returns the output so far, flushed so the client can
+    // decompress everything it got.
+    fn write(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let writer = self.writer();
+        writer.write_all(chunk)?;
+        writer.flush()?;
+        let buffer = match self {
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+        };
+        Ok(mem::take(buffer))
+    }
+
+    // Returns the end of the compressed body.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+fn compress(encoding: Encoding, body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(encoding);
+    encoder.writer().write_all(body)?;
+    encoder.finish()
+}
+
+// Parses the quality value of an `Accept-Encoding` member, e.g. `gzip;q=0.8`.
+fn parse_quality(params: &str) -> Option<f32> {
+    params.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        match name.trim().eq_ignore_ascii_case("q") {
+            true => value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|q| (0.0..=1.0).contains(q)),
+            false => None,
+        }
+    })
+}
+
+// Negotiates the coding of the response from the `Accept-Encoding` header, picking
+// the enabled coding with the highest quality, and the preferred one on equal
+// qualities. Codings not listed get the quality of `*`, when present.
+fn negotiate(accept_encoding: &str, enabled: &[Encoding]) -> Option<Encoding> {
+    let mut qualities: Vec<(String, f32)> = Vec::new();
+    for member in accept_encoding.split(',') {
+        let (coding, params) = member.split_once(';').unwrap_or((member, ""));
+        let coding = coding.trim().to_ascii_lowercase();
+        if coding.is_empty() {
+            continue;
+        }
+        qualities.push((coding, parse_quality(params).unwrap_or(1.0)));
+    }
+    let quality = |name: &str| {
+        qualities
+            .iter()
+            .find(|(coding, _)| coding == name)
+            .map(|(_, quality)| *quality)
+    };
+
+    let wildcard = quality("*").unwrap_or(0.0);
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in Encoding::ALL.iter().filter(|e| enabled.contains(e)) {
+        let quality = match encoding {
+            // `x-gzip` is an alias of `gzip` (RFC 9110, section 8.4.1.3)
+            Encoding::Gzip => quality("gzip").or_else(|| quality("x-gzip")),
+            _ => quality(encoding.as_str()),
+        }
+        .unwrap_or(wildcard);
+        if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((*encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    match essence.strip_prefix("text/") {
+        Some(subtype) => subtype != "event-stream",
+        None => {
+            COMPRESSIBLE_TYPES.contains(&essence.as_str())
+                || essence.ends_with("+json")
+                || essence.ends_with("+xml")
+        }
+    }
+}
+
+// Adds `Accept-Encoding` to the `Vary` header, as the response depends on it.
+fn add_vary(response: &mut HttpResponse) {
+    let varies = response.headers().get_all("Vary").any(|vary| {
+        vary.split(',').any(|name| {
+            let name = name.trim();
+            name == "*" || name.eq_ignore_ascii_case("Accept-Encoding")
+        })
+    });
+    if !varies {
+        response.headers_mut().append("Vary", "Accept-Encoding");
+    }
+}
+
+// Compresses the responses with the coding negotiated from the `Accept-Encoding`
+// header of the request: brotli, gzip or deflate. Only the responses with a
+// compressible content type and a body of at least `min_size` bytes are compressed,
+// streamed bodies being compressed chunk by chunk. Responses already encoded, partial
+// responses and the ones with `Cache-Control: no-transform` are left as they are.
+//
+// The compressed responses get a weak version of their entity tag, which still
+// matches the tag of the uncompressed representation in the conditional requests.
+#[derive(Debug, Clone)]
+pub struct CompressionMiddleware {
+    min_size: usize,
+    encodings: Vec<Encoding>,
+}
+
+impl CompressionMiddleware {
+    pub fn new() -> Self {
+        CompressionMiddleware {
+            min_size: DEFAULT_MIN_SIZE,
+            encodings: Encoding::ALL.to_vec(),
+        }
+    }
+
+    // Sets the size under which the bodies aren't compressed.
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    // Restricts the codings used to the given ones.
+    pub fn encodings(mut self, encodings: &[Encoding]) -> Self {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    // Compresses the response with the negotiated coding, when it is worth it.
+    fn compress_response(
+        &self,
+        mut response: HttpResponse,
+        encoding: Option<Encoding>,
+        head: bool,
+    ) -> HttpResponse {
+        let code: u16 = response.status().into();
+        let headers = response.headers();
+        let skipped = !response.status().allows_body()
+            || code == 206
+            || headers.contains("Content-Range")
+            || headers.contains("Content-Encoding")
+            || headers
+                .get_all("Cache-Control")
+                .any(|value| value.to_ascii_lowercase().contains("no-transform"))
+            || !headers.get("Content-Type").is_some_and(is_compressible);
+        if skipped {
+            return response;
+        }
+
+        // Bodies of HEAD responses may already be dropped, leaving only their length
+        let stripped = head && !response.is_stream() && response.body().is_empty();
+        let length = match response.is_stream() || stripped {
+            true => headers
+                .get("Content-Length")
+                .and_then(|length| length.trim().parse().ok()),
+            false => Some(response.body().len()),
+        };
+        if length.is_some_and(|length| length < self.min_size) {
+            return response;
+        }
+        add_vary(&mut response);
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => return response,
+        };
+
+        let body = match response.take_body() {
+            ResponseBody::Bytes(body) if stripped => {
+                // The compressed length is unknown without the body
+                response
+                    .headers_mut()
+                    .insert("Transfer-Encoding", "chunked");
+                ResponseBody::Bytes(body)
+            }
+            ResponseBody::Bytes(body) => match compress(encoding, &body) {
+                Ok(compressed) => ResponseBody::Bytes(compressed),
+                Err(_) => {
+                    response.set_body(ResponseBody::Bytes(body));
+                    return response;
+                }
+            },
+            ResponseBody::Stream(chunks) => {
+                let state = (chunks, Some(Encoder::new(encoding)));
+                let compressed = stream::unfold(state, |(mut chunks, encoder)| async move {
+                    let mut encoder = encoder?;
+                    loop {
+                        let output = match chunks.next().await {
+                            Some(Ok(chunk)) => encoder.write(&chunk),
+                            Some(Err(err)) => return Some((Err(err), (chunks, None))),
+                            None => return Some((encoder.finish(), (chunks, None))),
+                        };
+                        match output {
+                            Ok(output) if output.is_empty() => continue,
+                            Ok(output) => return Some((Ok(output), (chunks, Some(encoder)))),
+                            Err(err) => return Some((Err(err), (chunks, None))),
+                        }
+                    }
+                });
+                ResponseBody::Stream(compressed.boxed())
+            }
+        };
+        response.set_body(body);
+
+        let headers = response.headers_mut();
+        headers.remove("Content-Length");
+        headers.insert("Content-Encoding", encoding.as_str());
+        let etag = headers.get("ETag").and_then(ETag::parse);
+        if let Some(etag) = etag.filter(|etag| !etag.is_weak()) {
+            headers.insert("ETag", &ETag::weak(etag.tag()).to_string());
+        }
+        response
+    }
+}
+
+impl Middleware for CompressionMiddleware {
+    fn handle<'a>(&'a self, request: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse> {
+        let encoding = request
+            .header("Accept-Encoding")
+            .and_then(|accept_encoding| negotiate(accept_encoding, &self.encodings));
+        let head = request.method() == &MethodType::HEAD;
+        async move {
+            let response = next.run(request).await;
+            self.compress_response(response, encoding, head)
+        }
+        .boxed()
+    }
+}
+
+impl Default for CompressionMiddleware {
+    fn default() -> Self {
+        CompressionMiddleware::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression::{is_compressible, negotiate, CompressionMiddleware, Encoding};
+    use crate::request::HttpRequest;
+    use crate::response::{HttpResponse, ResponseBody};
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use futures::stream::{self, StreamExt};
+    use lightproc::prelude::ProcStack;
+    use std::io::{self, Read};
+
+    fn json() -> String {
+        let items: Vec<String> = (0..200).map(|i| format!("{{\"id\":{}}}", i)).collect();
+        format!("[{}]", items.join(","))
+    }
+
+    async fn large() -> HttpResponse {
+        HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "application/json")
+            .with_header("ETag", "\"v1\"")
+            .with_body(json())
+    }
+
+    async fn streamed() -> HttpResponse {
+        let body = json().into_bytes();
+        let chunks: Vec<io::Result<Vec<u8>>> = body.chunks(500).map(|c| Ok(c.to_vec())).collect();
+        HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain")
+            .with_stream(stream::iter(chunks))
+    }
+
+    async fn small() -> HttpResponse {
+        HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain")
+            .with_body("hello")
+    }
+
+    async fn image() -> HttpResponse {
+        HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "image/png")
+            .with_body(vec![0; 4096])
+    }
+
+    async fn encoded() -> HttpResponse {
+        HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain")
+            .with_header("Content-Encoding", "gzip")
+            .with_body(vec![b'a'; 4096])
+    }
+
+    async fn partial() -> HttpResponse {
+        HttpResponse::new(StatusCode::PartialContent)
+            .with_header("Content-Type", "text/plain")
+            .with_header("Content-Range", "bytes 0-4095/10000")
+            .with_body(vec![b'a'; 4096])
+    }
+
+    fn send(method: MethodType, path: &str, accept_encoding: &str) -> (HttpResponse, Vec<u8>) {
+        let router = Router::new()
+            .with_middleware(CompressionMiddleware::new())
+            .with_endpoint("/large", &["get"], large)
+            .with_endpoint("/streamed", &["get"], streamed)
+            .with_endpoint("/small", &["get"], small)
+            .with_endpoint("/image", &["get"], image)
+            .with_endpoint("/encoded", &["get"], encoded)
+            .with_endpoint("/partial", &["get"], partial);
+        let request =
+            HttpRequest::new(method, path).with_header("Accept-Encoding", accept_encoding);
+        let mut response = run(router.dispatch(request), ProcStack::default());
+        let body = match response.take_body() {
+            ResponseBody::Bytes(bytes) => bytes,
+            ResponseBody::Stream(stream) => {
+                let chunks = run(stream.collect::<Vec<_>>(), ProcStack::default());
+                chunks.into_iter().flat_map(Result::unwrap).collect()
+            }
+        };
+        (response, body)
+    }
+
+    fn decode(encoding: Option<&str>, body: &[u8]) -> String {
+        let mut decoded = String::new();
+        match encoding {
+            Some("gzip") => GzDecoder::new(body).read_to_string(&mut decoded),
+            Some("deflate") => ZlibDecoder::new(body).read_to_string(&mut decoded),
+            Some("br") => brotli::Decompressor::new(body, 4096).read_to_string(&mut decoded),
+            _ => panic!("unexpected encoding {:?}", encoding),
+        }
+        .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let all = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+        assert_eq!(negotiate("gzip, deflate, br", &all), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate("br;q=0.5, gzip;q=0.8", &all),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            negotiate("deflate, gzip;q=0", &all),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(negotiate("x-gzip", &all), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0.1, br;q=0", &all), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity", &all), None);
+        assert_eq!(negotiate("", &all), None);
+        assert_eq!(
+            negotiate("br, gzip", &[Encoding::Gzip]),
+            Some(Encoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn test_compressible_types() {
+        assert!(is_compressible("text/html; charset=utf-8"));
+        assert!(is_compressible("application/problem+json"));
+        assert!(is_compressible("Application/JSON"));
+        assert!(!is_compressible("text/event-stream"));
+        assert!(!is_compressible("image/png"));
+    }
+
+    #[test]
+    fn test_compress_buffered_body() {
+        for encoding in ["gzip", "deflate", "br"] {
+            let (response, body) = send(MethodType::GET, "/large", encoding);
+            let headers = response.headers();
+            assert_eq!(headers.get("Content-Encoding"), Some(encoding));
+            assert_eq!(headers.get("Vary"), Some("Accept-Encoding"));
+            assert_eq!(headers.get("ETag"), Some("W/\"v1\""));
+            assert_eq!(headers.get("Content-Length"), None);
+            assert!(body.len() < json().len());
+            assert_eq!(decode(Some(encoding), &body), json());
+        }
+
+        // Clients not accepting any coding still get the Vary header
+        let (response, body) = send(MethodType::GET, "/large", "identity");
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+        assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(body, json().into_bytes());
+    }
+
+    #[test]
+    fn test_compress_streamed_body() {
+        for encoding in ["gzip", "br"] {
+            let (response, body) = send(MethodType::GET, "/streamed", encoding);
+            assert_eq!(response.headers().get("Content-Encoding"), Some(encoding));
+            assert_eq!(decode(Some(encoding), &body), json());
+        }
+    }
+
+    #[test]
+    fn test_compress_head_response() {
+        let (response, body) = send(MethodType::HEAD, "/large", "gzip");
+        assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+        let length: usize = response
+            .headers()
+            .get("Content-Length")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(length < json().len());
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn test_skip_responses() {
+        let skipped = [
+            ("/small", None, 5),
+            ("/image", None, 4096),
+            ("/encoded", Some("gzip"), 4096),
+            ("/partial", None, 4096),
+        ];
+        for (path, content_encoding, length) in skipped {
+            let (response, body) = send(MethodType::GET, path, "gzip, br");
+            assert_eq!(response.headers().get("Vary"), None, "{}", path);
+            assert_eq!(
+                response.headers().get("Content-Encoding"),
+                content_encoding,
+                "{}",
+                path
+            );
+            assert_eq!(body.len(), length, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use crate::date;
+use crate::extract::{FromRequest, Rejection};
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::routing::endpoint::MethodType;
+use crate::status_code::StatusCode;
+use futures::future::{ready, BoxFuture, FutureExt};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Headers of a 200 response kept in the 304 Not Modified answer, so the caches can
+// update their stored response (RFC 9110, section 15.4.5).
+const NOT_MODIFIED_HEADERS: &[&str] = &[
+    "Cache-Control",
+    "Content-Location",
+    "Date",
+    "ETag",
+    "Expires",
+    "Last-Modified",
+    "Vary",
+];
+
+// Entity tag of a representation, sent in the `ETag` header. Strong tags change with
+// every byte of the representation, while weak ones only change when its meaning does.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+impl ETag {
+    // Builds a strong tag, without the quotes, which are removed from the tag.
+    pub fn strong(tag: &str) -> Self {
+        ETag {
+            tag: tag.replace('"', ""),
+            weak: false,
+        }
+    }
+
+    pub fn weak(tag: &str) -> Self {
+        ETag {
+            tag: tag.replace('"', ""),
+            weak: true,
+        }
+    }
+
+    // Parses the value of an `ETag` header, e.g. `"v1"` or `W/"v1"`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match parse_tag(value.trim())? {
+            (etag, "") => Some(etag),
+            _ => None,
+        }
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    // Strong comparison, matching only the identical strong tags.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    // Weak comparison, matching the tags with the same value.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+// Parses the entity tag at the start of the value, returning it with the rest.
+fn parse_tag(value: &str) -> Option<(ETag, &str)> {
+    let (weak, value) = match value.strip_prefix("W/") {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let value = value.strip_prefix('"')?;
+    let end = value.find('"')?;
+    let etag = ETag {
+        tag: value[..end].to_string(),
+        weak,
+    };
+    Some((etag, &value[end + 1..]))
+}
+
+// Value of the `If-Match` and `If-None-Match` headers.
+#[derive(Debug, Clone, PartialEq)]
+enum EntityTags {
+    Any,
+    List(Vec<ETag>),
+}
+
+impl EntityTags {
+    // Parses the `*` or the comma separated list of entity tags, skipping the
+    // invalid part of the list.
+    fn parse(value: &str) -> Self {
+        if value.trim() == "*" {
+            return EntityTags::Any;
+        }
+        let mut etags = Vec::new();
+        let mut rest = value;
+        loop {
+            rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+            match parse_tag(rest) {
+                Some((etag, remaining)) => {
+                    etags.push(etag);
+                    rest = remaining;
+                }
+                None => break,
+            }
+        }
+        EntityTags::List(etags)
+    }
+
+    fn matches(&self, current: Option<&ETag>, compare: fn(&ETag, &ETag) -> bool) -> bool {
+        match (self, current) {
+            (EntityTags::Any, _) => true,
+            (EntityTags::List(etags), Some(current)) => {
+                etags.iter().any(|etag| compare(etag, current))
+            }
+            (EntityTags::List(_), None) => false,
+        }
+    }
+}
+
+// Truncates the time to the second, the precision of the HTTP dates.
+fn to_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+// Conditional headers of a request, evaluated against the current validators of the
+// targeted resource. Handlers changing resources check them before doing it:
+//
+//     async fn update(preconditions: Preconditions, body: String) -> HttpResponse {
+//         let current = ETag::strong(&version());
+//         if let Some(status) = preconditions.evaluate(Some(&current), None) {
+//             return HttpResponse::new(status);
+//         }
+//         ...
+//     }
+//
+// Responses to GET and HEAD requests are checked by the `ConditionalMiddleware`.
+#[derive(Debug, Clone, Default)]
+pub struct Preconditions {
+    // Whether the request is a GET or HEAD, answered with 304 Not Modified
+    safe: bool,
+    if_match: Option<EntityTags>,
+    if_unmodified_since: Option<SystemTime>,
+    if_none_match: Option<EntityTags>,
+    if_modified_since: Option<SystemTime>,
+}
+
+impl Preconditions {
+    pub fn new(request: &HttpRequest) -> Self {
+        let date = |name| request.header(name).and_then(date::parse_http_date);
+        Preconditions {
+            safe: matches!(request.method(), MethodType::GET | MethodType::HEAD),
+            if_match: request.header("If-Match").map(EntityTags::parse),
+            if_unmodified_since: date("If-Unmodified-Since"),
+            if_none_match: request.header("If-None-Match").map(EntityTags::parse),
+            if_modified_since: date("If-Modified-Since"),
+        }
+    }
+
+    // Whether the request has no condition to evaluate.
+    pub fn is_empty(&self) -> bool {
+        self.if_match.is_none()
+            && self.if_unmodified_since.is_none()
+            && self.if_none_match.is_none()
+            && self.if_modified_since.is_none()
+    }
+
+    // Evaluates the conditions in the order of RFC 9110, section 13.2.2, returning
+    // 412 Precondition Failed or 304 Not Modified when one of them fails. The
+    // resource is expected to exist, with the given validators.
+    pub fn evaluate(
+        &self,
+        etag: Option<&ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Option<StatusCode> {
+        if let Some(if_match) = &self.if_match {
+            if !if_match.matches(etag, ETag::strong_eq) {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        } else if let (Some(since), Some(modified)) = (self.if_unmodified_since, last_modified) {
+            if to_seconds(modified) > to_seconds(since) {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        }
+
+        if let Some(if_none_match) = &self.if_none_match {
+            if if_none_match.matches(etag, ETag::weak_eq) {
+                return Some(match self.safe {
+                    true => StatusCode::NotModified,
+                    false => StatusCode::PreconditionFailed,
+                });
+            }
+        } else if let (true, Some(since), Some(modified)) =
+            (self.safe, self.if_modified_since, last_modified)
+        {
+            if to_seconds(modified) <= to_seconds(since) {
+                return Some(StatusCode::NotModified);
+            }
+        }
+        None
+    }
+
+    // Evaluates the conditions against the validators of a successful response,
+    // turning it into the matching 304 or 412 response.
+    pub fn apply(&self, response: HttpResponse) -> HttpResponse {
+        let code: u16 = response.status().into();
+        if self.is_empty() || !(200..300).contains(&code) {
+            return response;
+        }
+        let headers = response.headers();
+        let etag = headers.get("ETag").and_then(ETag::parse);
+        let last_modified = headers.get("Last-Modified").and_then(date::parse_http_date);
+
+        match self.evaluate(etag.as_ref(), last_modified) {
+            Some(StatusCode::NotModified) => not_modified(&response),
+            Some(status) => HttpResponse::new(status),
+            None => response,
+        }
+    }
+}
+
+impl FromRequest for Preconditions {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        ready(Ok(Preconditions::new(request))).boxed()
+    }
+}
+
+fn not_modified(response: &HttpResponse) -> HttpResponse {
+    let mut not_modified = HttpResponse::new(StatusCode::NotModified);
+    for name in NOT_MODIFIED_HEADERS {
+        for value in response.headers().get_all(name) {
+            not_modified.headers_mut().append(name, value);
+        }
+    }
+    not_modified
+}
+
+// Answers the conditional GET and HEAD requests with 304 Not Modified or 412
+// Precondition Failed, using the `ETag` and `Last-Modified` headers of the
+// responses. The handler still builds the whole response, which is then dropped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConditionalMiddleware;
+
+impl Middleware for ConditionalMiddleware {
+    fn handle<'a>(&'a self, request: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse> {
+        let preconditions = Preconditions::new(&request);
+        async move {
+            let response = next.run(request).await;
+            match preconditions.safe {
+                true => preconditions.apply(response),
+                false => response,
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conditional::{ConditionalMiddleware, ETag, EntityTags, Preconditions};
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    async fn page() -> HttpResponse {
+        HttpResponse::new(StatusCode::Ok)
+            .with_etag(&ETag::strong("v2"))
+            .with_last_modified(UNIX_EPOCH + Duration::from_secs(784_111_777))
+            .with_header("Cache-Control", "max-age=60")
+            .with_body("page")
+    }
+
+    fn send(method: MethodType, headers: &[(&str, &str)]) -> HttpResponse {
+        let router = Router::new()
+            .with_middleware(ConditionalMiddleware)
+            .with_endpoint("/", &["get", "post"], page);
+        let mut request = HttpRequest::new(method, "/");
+        for (name, value) in headers {
+            request = request.with_header(name, value);
+        }
+        run(router.dispatch(request), ProcStack::default())
+    }
+
+    #[test]
+    fn test_parse_etags() {
+        assert_eq!(ETag::parse("\"v1\""), Some(ETag::strong("v1")));
+        assert_eq!(ETag::parse(" W/\"v1\" "), Some(ETag::weak("v1")));
+        assert_eq!(ETag::parse("v1"), None);
+        assert_eq!(ETag::weak("v1").to_string(), "W/\"v1\"");
+        assert_eq!(
+            EntityTags::parse("\"a\", W/\"b\",\"c,d\""),
+            EntityTags::List(vec![
+                ETag::strong("a"),
+                ETag::weak("b"),
+                ETag::strong("c,d")
+            ])
+        );
+        assert_eq!(EntityTags::parse(" * "), EntityTags::Any);
+
+        assert!(ETag::weak("a").weak_eq(&ETag::strong("a")));
+        assert!(!ETag::weak("a").strong_eq(&ETag::strong("a")));
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let response = send(MethodType::GET, &[("If-None-Match", "W/\"v1\", W/\"v2\"")]);
+        assert_eq!(response.status(), StatusCode::NotModified);
+        assert_eq!(response.headers().get("ETag"), Some("\"v2\""));
+        assert_eq!(response.headers().get("Cache-Control"), Some("max-age=60"));
+        assert_eq!(response.headers().get("Content-Type"), None);
+        assert!(response.body().is_empty());
+
+        let since = [("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")];
+        assert_eq!(
+            send(MethodType::GET, &since).status(),
+            StatusCode::NotModified
+        );
+        let since = [("If-Modified-Since", "Sun, 06 Nov 1994 08:49:36 GMT")];
+        assert_eq!(send(MethodType::GET, &since).status(), StatusCode::Ok);
+
+        // If-None-Match takes precedence over If-Modified-Since
+        let headers = [
+            ("If-None-Match", "\"v1\""),
+            ("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ];
+        assert_eq!(send(MethodType::GET, &headers).status(), StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_precondition_failed() {
+        let response = send(MethodType::GET, &[("If-Match", "\"v1\"")]);
+        assert_eq!(response.status(), StatusCode::PreconditionFailed);
+        assert!(response.body().is_empty());
+        assert_eq!(
+            send(MethodType::GET, &[("If-Match", "\"v2\"")]).status(),
+            StatusCode::Ok
+        );
+        assert_eq!(
+            send(MethodType::GET, &[("If-Match", "W/\"v2\"")]).status(),
+            StatusCode::PreconditionFailed
+        );
+
+        let since = [("If-Unmodified-Since", "Sun, 06 Nov 1994 08:49:36 GMT")];
+        assert_eq!(
+            send(MethodType::GET, &since).status(),
+            StatusCode::PreconditionFailed
+        );
+        // If-Match takes precedence over If-Unmodified-Since
+        let headers = [("If-Match", "*"), since[0]];
+        assert_eq!(send(MethodType::GET, &headers).status(), StatusCode::Ok);
+
+        // The middleware leaves the other methods to their handler
+        let response = send(MethodType::POST, &[("If-Match", "\"v1\"")]);
+        assert_eq!(response.status(), StatusCode::Ok);
+    }
+
+    #[test]
+    fn test_evaluate_unsafe_methods() {
+        let request = HttpRequest::new(MethodType::PUT, "/").with_header("If-None-Match", "*");
+        let preconditions = Preconditions::new(&request);
+        assert_eq!(
+            preconditions.evaluate(Some(&ETag::strong("v1")), None),
+            Some(StatusCode::PreconditionFailed)
+        );
+
+        let request = HttpRequest::new(MethodType::PUT, "/")
+            .with_header("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT");
+        let preconditions = Preconditions::new(&request);
+        assert_eq!(preconditions.evaluate(None, Some(UNIX_EPOCH)), None);
+        assert!(Preconditions::new(&HttpRequest::new(MethodType::GET, "/")).is_empty());
+    }
+}
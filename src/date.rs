@@ -0,0 +1,131 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// Converts days since the epoch into a (year, month, day) date, see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// Formats the time as an HTTP date (e.g. `Sun, 06 Nov 1994 08:49:37 GMT`).
+pub(crate) fn format_http_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let days = secs.div_euclid(86_400);
+    let time = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|part| match part.len() {
+        2 => part.parse::<u64>().ok(),
+        _ => None,
+    });
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+fn parse_month(month: &str) -> Option<u32> {
+    MONTHS
+        .iter()
+        .position(|name| *name == month)
+        .map(|index| index as u32 + 1)
+}
+
+// Parses an HTTP date in any of the formats recipients must accept (RFC 9110,
+// section 5.6.7): `Sun, 06 Nov 1994 08:49:37 GMT`, the obsolete
+// `Sunday, 06-Nov-94 08:49:37 GMT` and the asctime `Sun Nov  6 08:49:37 1994`.
+pub(crate) fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let (year, month, day, time) = match parts.as_slice() {
+        [_, day, month, year, time, "GMT"] => (year.parse().ok()?, *month, *day, *time),
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            let year: i64 = year.parse().ok()?;
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            (year, month, day, *time)
+        }
+        [_, month, day, time, year] => (year.parse().ok()?, *month, *day, *time),
+        _ => return None,
+    };
+    let month = parse_month(month)?;
+    let day: u32 = day.parse().ok()?;
+    if !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day) as u64;
+    let secs = days * 86_400 + parse_time(time)?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::date::{format_http_date, parse_http_date};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_format_http_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        let time = UNIX_EPOCH + Duration::from_secs(951_825_600);
+        assert_eq!(format_http_date(time), "Tue, 29 Feb 2000 12:00:00 GMT");
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+        for date in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(date), expected, "{}", date);
+        }
+        for date in [
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "now",
+        ] {
+            assert_eq!(parse_http_date(date), None, "{}", date);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use httparse::Error as HttpParseError;
 use std::io::Error as IOError;
 use std::result;
+use std::time::Duration;
 use thiserror::Error;
 
 // Alias for easier error handling and less boilerplate.
@@ -16,12 +17,12 @@ pub enum AlcazarError {
     ParseError(#[from] ParseError),
     #[error(transparent)]
     RoutingError(#[from] RoutingError),
+    #[error(transparent)]
+    BodyError(#[from] BodyError),
 }
 
 #[derive(Error, Debug, Clone)]
 pub enum HttpError {
-    #[error("partial content sended: status code 206")]
-    PartialContent,
     #[error("internal server error: status code 500")]
     InternalServerError,
     #[error("method not implemented: status code 501")]
@@ -36,6 +37,56 @@ pub enum ParseError {
     MethodMissing,
     #[error("path is missing in the request")]
     PathMissing,
+    #[error("the connection was closed before the end of the request head")]
+    IncompleteRequest,
+    #[error("{0:?} is not a valid method name")]
+    InvalidMethod(String),
+    #[error("{0:?} is not a valid content length")]
+    InvalidContentLength(String),
+    #[error("{0:?} is not a supported transfer coding")]
+    UnsupportedTransferEncoding(String),
+}
+
+#[derive(Error, Debug)]
+pub enum MultipartError {
+    #[error("multipart body ended before the closing boundary")]
+    Incomplete,
+    #[error("invalid multipart body: {0}")]
+    Invalid(String),
+    #[error("the {name:?} field is larger than the limit of {limit} bytes")]
+    FieldTooLarge { name: String, limit: usize },
+    #[error("multipart body is larger than the limit of {0} bytes")]
+    TooLarge(usize),
+    #[error(transparent)]
+    IOError(#[from] IOError),
+}
+
+#[derive(Error, Debug)]
+pub enum BodyError {
+    #[error("body is larger than the limit of {0} bytes")]
+    TooLarge(usize),
+    #[error(transparent)]
+    IOError(#[from] IOError),
+}
+
+#[derive(Error, Debug)]
+pub enum WebSocketError {
+    #[error("WebSocket protocol error: {0}")]
+    Protocol(String),
+    #[error("text message isn't valid UTF-8")]
+    InvalidUtf8,
+    #[error("message is larger than the limit of {0} bytes")]
+    MessageTooLarge(usize),
+    #[error("the WebSocket connection is closed")]
+    ConnectionClosed,
+    #[error(transparent)]
+    IOError(#[from] IOError),
+}
+
+#[derive(Error, Debug)]
+pub enum ServiceError {
+    #[error("the service didn't respond within {0:?}")]
+    Timeout(Duration),
 }
 
 #[derive(Error, Debug, Clone)]
@@ -44,4 +95,37 @@ pub enum RoutingError {
     InvalidPathError { part: String, path: String },
     #[error("can't compile {0} regex for the given path.")]
     RegexCompileError(String),
+    #[error("found an invalid {part:?} part of the {host:?} host.")]
+    InvalidHostError { part: String, host: String },
+    #[error("unknown {constraint:?} constraint in the {path:?} path.")]
+    InvalidConstraintError { constraint: String, path: String },
+    #[error("found an invalid {method:?} method for the {path:?} path.")]
+    InvalidMethodError { method: String, path: String },
+    #[error("the {path:?} path is declared more than once for {methods} methods.")]
+    DuplicateRouteError { methods: String, path: String },
+    #[error("the {path:?} path overlaps with the {other:?} path for {methods} methods.")]
+    AmbiguousRouteError {
+        methods: String,
+        path: String,
+        other: String,
+    },
+    #[error("the {0:?} name is given to more than one route.")]
+    DuplicateNameError(String),
+    #[error("there is no route named {0:?}.")]
+    UnknownRouteError(String),
+    #[error("missing value for the {param:?} parameter of the {route:?} route.")]
+    MissingParamError { param: String, route: String },
+    #[error("invalid {value:?} value for the {param:?} parameter of the {route:?} route.")]
+    InvalidParamError {
+        param: String,
+        value: String,
+        route: String,
+    },
+    #[error("found invalid routes: {}", join_errors(.0))]
+    InvalidRoutesError(Vec<RoutingError>),
+}
+
+fn join_errors(errors: &[RoutingError]) -> String {
+    let messages: Vec<String> = errors.iter().map(RoutingError::to_string).collect();
+    messages.join(" ")
 }
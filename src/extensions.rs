@@ -0,0 +1,135 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// Type map holding one value per type, used for the values attached to a request,
+// like the ones middleware pass down to the handlers.
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions::default()
+    }
+
+    // Inserts the value, returning the previous one of the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+// Shared application state registered with `AppBuilder::with_state`, one value per
+// type. The values are shared by all requests, so they need their own
+// synchronization (e.g. a `Mutex`) to be modified.
+#[derive(Clone, Default)]
+pub struct AppState {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        AppState::default()
+    }
+
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.map.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub(crate) fn get_shared<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.clone().downcast().ok())
+    }
+}
+
+impl fmt::Debug for AppState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppState")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extensions::{AppState, Extensions};
+
+    #[derive(Debug, PartialEq)]
+    struct User(&'static str);
+
+    #[test]
+    fn test_extensions_hold_one_value_per_type() {
+        let mut extensions = Extensions::new();
+        assert_eq!(extensions.insert(User("alice")), None);
+        assert_eq!(extensions.insert(User("bob")), Some(User("alice")));
+        extensions.insert(42u32);
+
+        assert_eq!(extensions.get::<User>(), Some(&User("bob")));
+        *extensions.get_mut::<u32>().unwrap() += 1;
+        assert_eq!(extensions.remove::<u32>(), Some(43));
+        assert!(!extensions.contains::<u32>());
+        assert_eq!(extensions.len(), 1);
+    }
+
+    #[test]
+    fn test_app_state_shares_values() {
+        let mut state = AppState::new();
+        state.insert(User("alice"));
+
+        let shared = state.get_shared::<User>().unwrap();
+        assert_eq!(*shared, User("alice"));
+        assert_eq!(state.get::<User>(), Some(&User("alice")));
+        assert!(state.get::<u32>().is_none());
+    }
+}
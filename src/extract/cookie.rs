@@ -0,0 +1,252 @@
+use crate::extract::{FromRequest, Rejection};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, IntoResponse};
+use crate::status_code::StatusCode;
+use cookie::CookieJar as Jar;
+use futures::future::{ready, BoxFuture, FutureExt};
+use tracing::warn;
+
+pub use cookie::{time, Cookie, CookieBuilder, Expiration, Key, SameSite};
+
+// Parses the `Cookie` headers, skipping the invalid cookies.
+pub(crate) fn parse_jar(request: &HttpRequest) -> Jar {
+    let mut jar = Jar::new();
+    for header in request.headers().get_all("Cookie") {
+        for cookie in Cookie::split_parse_encoded(header.to_string()) {
+            match cookie {
+                Ok(cookie) => jar.add_original(cookie.into_owned()),
+                Err(err) => warn!("skipping an invalid cookie: {}", err),
+            }
+        }
+    }
+    jar
+}
+
+// Returns the key of the signed and private jars, registered as application state.
+fn cookie_key(request: &HttpRequest) -> Result<Key, Rejection> {
+    request.state::<Key>().cloned().ok_or_else(|| {
+        Rejection::new(
+            StatusCode::InternalServerError,
+            "missing cookie key in the application state",
+        )
+    })
+}
+
+// Adds the changes made to the jar to the response, as `Set-Cookie` headers.
+fn write_delta(jar: &Jar, response: &mut HttpResponse) {
+    for cookie in jar.delta() {
+        response
+            .headers_mut()
+            .append("Set-Cookie", &cookie.encoded().to_string());
+    }
+}
+
+// Cookies of the request. The cookies added or removed are sent back when the jar
+// is returned with the response, as in `(jar, "body")`.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    jar: Jar,
+}
+
+impl CookieJar {
+    pub fn get(&self, name: &str) -> Option<&Cookie<'static>> {
+        self.jar.get(name)
+    }
+
+    pub fn add<C: Into<Cookie<'static>>>(&mut self, cookie: C) {
+        self.jar.add(cookie);
+    }
+
+    // Removes the cookie from the client, which requires the same path and domain
+    // as the ones it was set with.
+    pub fn remove<C: Into<Cookie<'static>>>(&mut self, cookie: C) {
+        self.jar.remove(cookie);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cookie<'static>> {
+        self.jar.iter()
+    }
+}
+
+impl FromRequest for CookieJar {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        ready(Ok(CookieJar {
+            jar: parse_jar(request),
+        }))
+        .boxed()
+    }
+}
+
+// Cookies authenticated with the `Key` of the application state, so the client can
+// read but not modify them. Cookies failing the verification are left out.
+#[derive(Clone)]
+pub struct SignedCookieJar {
+    jar: Jar,
+    key: Key,
+}
+
+impl SignedCookieJar {
+    pub fn get(&self, name: &str) -> Option<Cookie<'static>> {
+        self.jar.signed(&self.key).get(name)
+    }
+
+    pub fn add<C: Into<Cookie<'static>>>(&mut self, cookie: C) {
+        self.jar.signed_mut(&self.key).add(cookie);
+    }
+
+    pub fn remove<C: Into<Cookie<'static>>>(&mut self, cookie: C) {
+        self.jar.signed_mut(&self.key).remove(cookie);
+    }
+}
+
+impl FromRequest for SignedCookieJar {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let result = cookie_key(request).map(|key| SignedCookieJar {
+            jar: parse_jar(request),
+            key,
+        });
+        ready(result).boxed()
+    }
+}
+
+// Cookies encrypted and authenticated with the `Key` of the application state, so
+// the client can neither read nor modify them. Cookies failing the decryption are
+// left out.
+#[derive(Clone)]
+pub struct PrivateCookieJar {
+    jar: Jar,
+    key: Key,
+}
+
+impl PrivateCookieJar {
+    pub fn get(&self, name: &str) -> Option<Cookie<'static>> {
+        self.jar.private(&self.key).get(name)
+    }
+
+    pub fn add<C: Into<Cookie<'static>>>(&mut self, cookie: C) {
+        self.jar.private_mut(&self.key).add(cookie);
+    }
+
+    pub fn remove<C: Into<Cookie<'static>>>(&mut self, cookie: C) {
+        self.jar.private_mut(&self.key).remove(cookie);
+    }
+}
+
+impl FromRequest for PrivateCookieJar {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let result = cookie_key(request).map(|key| PrivateCookieJar {
+            jar: parse_jar(request),
+            key,
+        });
+        ready(result).boxed()
+    }
+}
+
+macro_rules! impl_into_response {
+    ($($jar:ident),*) => {
+        $(
+            impl<T: IntoResponse> IntoResponse for ($jar, T) {
+                fn into_response(self) -> HttpResponse {
+                    let (jar, body) = self;
+                    let mut response = body.into_response();
+                    write_delta(&jar.jar, &mut response);
+                    response
+                }
+            }
+        )*
+    };
+}
+
+impl_into_response!(CookieJar, SignedCookieJar, PrivateCookieJar);
+
+#[cfg(test)]
+mod tests {
+    use crate::extensions::AppState;
+    use crate::extract::cookie::{
+        Cookie, CookieJar, Key, PrivateCookieJar, SameSite, SignedCookieJar,
+    };
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use std::sync::Arc;
+
+    async fn visit(mut jar: CookieJar) -> (CookieJar, String) {
+        let visits: u32 = jar
+            .get("visits")
+            .and_then(|cookie| cookie.value().parse().ok())
+            .unwrap_or(0);
+        jar.add(
+            Cookie::build(("visits", (visits + 1).to_string()))
+                .path("/")
+                .http_only(true),
+        );
+        jar.remove(Cookie::build("theme").path("/"));
+        (jar, format!("{} visits", visits))
+    }
+
+    async fn login(
+        mut signed: SignedCookieJar,
+        mut private: PrivateCookieJar,
+    ) -> (SignedCookieJar, (PrivateCookieJar, String)) {
+        let user = signed.get("user").map(|cookie| cookie.value().to_string());
+        let token = private
+            .get("token")
+            .map(|cookie| cookie.value().to_string());
+        signed.add(Cookie::new("user", "alice"));
+        private.add(
+            Cookie::build(("token", "s3cr3t"))
+                .same_site(SameSite::Strict)
+                .secure(true),
+        );
+        (signed, (private, format!("{:?} {:?}", user, token)))
+    }
+
+    fn dispatch(router: &Router, cookie: &str, state: Arc<AppState>) -> HttpResponse {
+        let mut request = HttpRequest::new(MethodType::GET, "/").with_header("Cookie", cookie);
+        request.set_state(state);
+        run(router.dispatch(request), ProcStack::default())
+    }
+
+    #[test]
+    fn test_read_and_write_cookies() {
+        let router = Router::new().with_endpoint("/", &["get"], visit);
+
+        let response = dispatch(&router, "visits=2; theme=dark%20blue", Arc::default());
+        assert_eq!(response.body(), b"2 visits");
+        let mut cookies: Vec<&str> = response.headers().get_all("Set-Cookie").collect();
+        cookies.sort_unstable();
+        assert!(cookies[0].starts_with("theme=; Path=/; Max-Age=0; Expires="));
+        assert_eq!(cookies[1], "visits=3; HttpOnly; Path=/");
+    }
+
+    #[test]
+    fn test_signed_and_private_cookies() {
+        let router = Router::new().with_endpoint("/", &["get"], login);
+        let mut state = AppState::new();
+        state.insert(Key::generate());
+        let state = Arc::new(state);
+
+        let response = dispatch(&router, "user=mallory; token=forged", state.clone());
+        assert_eq!(response.body(), b"None None");
+
+        // Send back the cookies issued by the server
+        let cookies: Vec<String> = response
+            .headers()
+            .get_all("Set-Cookie")
+            .map(|cookie| cookie.split(';').next().unwrap().to_string())
+            .collect();
+        assert!(!cookies.iter().any(|cookie| cookie.contains("s3cr3t")));
+        let response = dispatch(&router, &cookies.join("; "), state);
+        assert_eq!(response.body(), b"Some(\"alice\") Some(\"s3cr3t\")");
+
+        // The key is missing from the application state
+        let response = dispatch(&router, "", Arc::default());
+        assert_eq!(
+            response.status(),
+            crate::status_code::StatusCode::InternalServerError
+        );
+    }
+}
@@ -0,0 +1,478 @@
+use crate::routing::pattern::Constraint;
+use serde::de::{
+    self, value::BorrowedStrDeserializer, DeserializeOwned, DeserializeSeed, MapAccess, SeqAccess,
+    Visitor,
+};
+use serde::forward_to_deserialize_any;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeError {
+    message: String,
+    // Whether the rejected value satisfied the constraint of its route parameter
+    constrained: bool,
+}
+
+impl DeError {
+    fn new(message: String) -> Self {
+        DeError {
+            message,
+            constrained: false,
+        }
+    }
+
+    // Tells whether a value allowed by the constraint of its route parameter couldn't
+    // be deserialized, that is the route and the type it's deserialized into disagree.
+    pub(crate) fn is_constraint_mismatch(&self) -> bool {
+        self.constrained
+    }
+}
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DeError::new(msg.to_string())
+    }
+}
+
+// Deserializes the value from the list of key/value pairs, like route parameters or
+// query string fields. Structs and maps take the values by key, where repeated keys
+// can fill sequences, tuples take the values in order, and a single pair can be
+// deserialized into a plain value (e.g. `Path<u32>` for the `/users/{id}` route).
+pub(crate) fn from_pairs<T: DeserializeOwned>(pairs: &[(String, String)]) -> Result<T, DeError> {
+    from_params(pairs, &[])
+}
+
+// Same as `from_pairs` for route parameters, where the values that satisfy the
+// constraint of their parameter but can't be parsed are reported as mismatches.
+pub(crate) fn from_params<T: DeserializeOwned>(
+    params: &[(String, String)],
+    constraints: &[(String, Constraint)],
+) -> Result<T, DeError> {
+    T::deserialize(PairsDeserializer {
+        pairs: params,
+        constraints,
+    })
+}
+
+struct PairsDeserializer<'de> {
+    pairs: &'de [(String, String)],
+    constraints: &'de [(String, Constraint)],
+}
+
+impl<'de> PairsDeserializer<'de> {
+    fn constraint(&self, key: &str) -> Option<&'de Constraint> {
+        self.constraints
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, constraint)| constraint)
+    }
+
+    fn single_value(&self) -> Result<ValueDeserializer<'de>, DeError> {
+        match self.pairs {
+            [(key, value)] => Ok(ValueDeserializer {
+                values: vec![value.as_str()],
+                constraint: self.constraint(key),
+            }),
+            pairs => Err(DeError::new(format!(
+                "expected a single value, found {}",
+                pairs.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! forward_to_single_value {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                self.single_value()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for PairsDeserializer<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        // Group the values of repeated keys, keeping the order of the first appearance
+        let mut entries: Vec<(&'de str, Vec<&'de str>)> = Vec::new();
+        for (key, value) in self.pairs {
+            match entries.iter_mut().find(|(entry_key, _)| entry_key == key) {
+                Some((_, values)) => values.push(value),
+                None => entries.push((key, vec![value])),
+            }
+        }
+        visitor.visit_map(PairsMap {
+            entries: entries.into_iter(),
+            value: None,
+            constraints: self.constraints,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let values: Vec<(&'de str, Option<&'de Constraint>)> = self
+            .pairs
+            .iter()
+            .map(|(key, value)| (value.as_str(), self.constraint(key)))
+            .collect();
+        visitor.visit_seq(ValuesSeq {
+            values: values.into_iter(),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        if self.pairs.len() != len {
+            return Err(DeError::new(format!(
+                "expected {} values, found {}",
+                len,
+                self.pairs.len()
+            )));
+        }
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.single_value()?
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_single_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_identifier
+    }
+}
+
+struct PairsMap<'de, I> {
+    entries: I,
+    value: Option<(Vec<&'de str>, Option<&'de Constraint>)>,
+    constraints: &'de [(String, Constraint)],
+}
+
+impl<'de, I> MapAccess<'de> for PairsMap<'de, I>
+where
+    I: Iterator<Item = (&'de str, Vec<&'de str>)>,
+{
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, DeError> {
+        match self.entries.next() {
+            Some((key, values)) => {
+                let constraint = self
+                    .constraints
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, constraint)| constraint);
+                self.value = Some((values, constraint));
+                seed.deserialize(BorrowedStrDeserializer::new(key))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DeError> {
+        let (values, constraint) = self.value.take().unwrap_or_default();
+        seed.deserialize(ValueDeserializer { values, constraint })
+    }
+}
+
+struct ValuesSeq<I> {
+    values: I,
+}
+
+impl<'de, I> SeqAccess<'de> for ValuesSeq<I>
+where
+    I: Iterator<Item = (&'de str, Option<&'de Constraint>)>,
+{
+    type Error = DeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, DeError> {
+        match self.values.next() {
+            Some((value, constraint)) => seed
+                .deserialize(ValueDeserializer {
+                    values: vec![value],
+                    constraint,
+                })
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// All the values given for a key. Plain types take the first one, and sequences take all.
+struct ValueDeserializer<'de> {
+    values: Vec<&'de str>,
+    constraint: Option<&'de Constraint>,
+}
+
+impl<'de> ValueDeserializer<'de> {
+    fn first(&self) -> Result<&'de str, DeError> {
+        self.values
+            .first()
+            .copied()
+            .ok_or_else(|| DeError::new("missing value".to_string()))
+    }
+
+    fn parse<T: std::str::FromStr>(&self, expected: &str) -> Result<T, DeError> {
+        let value = self.first()?;
+        value.parse().map_err(|_| match self.constraint {
+            Some(constraint) => DeError {
+                message: format!(
+                    "value {:?} satisfies the `{}` constraint of the route but isn't {}",
+                    value,
+                    constraint.regex(),
+                    expected
+                ),
+                constrained: true,
+            },
+            None => DeError::new(format!("invalid value {:?}, expected {}", value, expected)),
+        })
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident, $expected:expr;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+                visitor.$visit(self.parse($expected)?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        match self.values.len() {
+            1 => visitor.visit_borrowed_str(self.values[0]),
+            _ => self.deserialize_seq(visitor),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool, "a boolean";
+        deserialize_i8 => visit_i8, "an integer";
+        deserialize_i16 => visit_i16, "an integer";
+        deserialize_i32 => visit_i32, "an integer";
+        deserialize_i64 => visit_i64, "an integer";
+        deserialize_u8 => visit_u8, "a non-negative integer";
+        deserialize_u16 => visit_u16, "a non-negative integer";
+        deserialize_u32 => visit_u32, "a non-negative integer";
+        deserialize_u64 => visit_u64, "a non-negative integer";
+        deserialize_f32 => visit_f32, "a number";
+        deserialize_f64 => visit_f64, "a number";
+        deserialize_char => visit_char, "a character";
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_borrowed_str(self.first()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_borrowed_bytes(self.first()?.as_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        let constraint = self.constraint;
+        visitor.visit_seq(ValuesSeq {
+            values: self.values.into_iter().map(|value| (value, constraint)),
+        })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError> {
+        let variant = BorrowedStrDeserializer::<DeError>::new(self.first()?);
+        visitor.visit_enum(variant)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DeError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        unit_struct tuple_struct map struct identifier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extract::de::from_pairs;
+    use serde::Deserialize;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Order {
+        Asc,
+        Desc,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Search {
+        q: String,
+        page: Option<u32>,
+        tag: Vec<String>,
+        order: Order,
+    }
+
+    #[test]
+    fn test_deserialize_struct_with_repeated_keys() {
+        let search: Search = from_pairs(&pairs(&[
+            ("q", "rust"),
+            ("tag", "web"),
+            ("order", "desc"),
+            ("tag", "async"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            search,
+            Search {
+                q: "rust".to_string(),
+                page: None,
+                tag: vec!["web".to_string(), "async".to_string()],
+                order: Order::Desc,
+            }
+        );
+    }
+
+    #[test]
+    fn test_deserialize_single_value_and_tuple() {
+        let id: u32 = from_pairs(&pairs(&[("id", "42")])).unwrap();
+        assert_eq!(id, 42);
+
+        let params: (String, i64) = from_pairs(&pairs(&[("name", "bob"), ("id", "-1")])).unwrap();
+        assert_eq!(params, ("bob".to_string(), -1));
+    }
+
+    #[test]
+    fn test_get_error_for_invalid_values() {
+        let result = from_pairs::<u32>(&pairs(&[("id", "abc")]));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "invalid value \"abc\", expected a non-negative integer"
+        );
+
+        assert!(from_pairs::<u32>(&pairs(&[])).is_err());
+        assert!(from_pairs::<Search>(&pairs(&[("page", "1")])).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::extract::de::from_pairs;
+use crate::extract::{read_body, FromRequest, Rejection};
+use crate::request::HttpRequest;
+use crate::status_code::StatusCode;
+use futures::future::{BoxFuture, FutureExt};
+use percent_encoding::percent_decode;
+use serde::de::DeserializeOwned;
+use std::ops::{Deref, DerefMut};
+
+// Body of an HTML form, posted with the `application/x-www-form-urlencoded` content
+// type and deserialized like the query string, so repeated keys can be collected
+// into a `Vec`. The body is decoded with its `charset` parameter, which may be UTF-8
+// (the default) or ISO-8859-1; other ones are rejected with 415 Unsupported Media Type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Form<T>(pub T);
+
+impl_deref!(Form);
+
+#[derive(Clone, Copy)]
+enum Charset {
+    Utf8,
+    Latin1,
+}
+
+impl Charset {
+    fn from_request(request: &HttpRequest) -> Result<Self, Rejection> {
+        let charset = request.header("Content-Type").and_then(|content_type| {
+            content_type.split(';').skip(1).find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("charset") {
+                    Some(value.trim().trim_matches('"').to_ascii_lowercase())
+                } else {
+                    None
+                }
+            })
+        });
+        match charset.as_deref() {
+            None | Some("utf-8") | Some("utf8") | Some("us-ascii") => Ok(Charset::Utf8),
+            Some("iso-8859-1") | Some("latin1") => Ok(Charset::Latin1),
+            Some(charset) => Err(Rejection::new(
+                StatusCode::UnsupportedMediaType,
+                &format!("unsupported {:?} charset of the form", charset),
+            )),
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Result<String, Rejection> {
+        match self {
+            Charset::Utf8 => String::from_utf8(bytes.to_vec())
+                .map_err(|_| Rejection::bad_request("form isn't valid UTF-8")),
+            // The first 256 code points of Unicode are the ISO-8859-1 characters
+            Charset::Latin1 => Ok(bytes.iter().map(|&byte| char::from(byte)).collect()),
+        }
+    }
+}
+
+// Splits the body into the key/value pairs, decoding them once unescaped.
+fn parse_pairs(body: &[u8], charset: Charset) -> Result<Vec<(String, String)>, Rejection> {
+    let decode = |part: &[u8]| {
+        let part: Vec<u8> = part
+            .iter()
+            .map(|&byte| if byte == b'+' { b' ' } else { byte })
+            .collect();
+        let unescaped: Vec<u8> = percent_decode(&part).collect();
+        charset.decode(&unescaped)
+    };
+
+    body.split(|&byte| byte == b'&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut parts = pair.splitn(2, |&byte| byte == b'=');
+            let key = decode(parts.next().unwrap_or(b""))?;
+            let value = decode(parts.next().unwrap_or(b""))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Form<T> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        async move {
+            if request.media_type().as_deref() != Some("application/x-www-form-urlencoded") {
+                return Err(Rejection::new(
+                    StatusCode::UnsupportedMediaType,
+                    "expected a request with the `application/x-www-form-urlencoded` content type",
+                ));
+            }
+            let charset = Charset::from_request(request)?;
+            let body = read_body(request).await?;
+            let pairs = parse_pairs(&body, charset)?;
+            from_pairs(&pairs).map(Form).map_err(|err| {
+                Rejection::new(
+                    StatusCode::UnprocessableEntity,
+                    &format!("invalid form: {}", err),
+                )
+            })
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extract::{BodyLimit, Form};
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Signup {
+        name: String,
+        newsletter: Option<bool>,
+        #[serde(default)]
+        topic: Vec<String>,
+    }
+
+    async fn signup(Form(form): Form<Signup>) -> String {
+        format!("{} {:?} {:?}", form.name, form.newsletter, form.topic)
+    }
+
+    fn post(router: &Router, content_type: &str, body: Vec<u8>) -> HttpResponse {
+        let request = HttpRequest::new(MethodType::POST, "/signup")
+            .with_header("Content-Type", content_type)
+            .with_body(body);
+        run(router.dispatch(request), ProcStack::default())
+    }
+
+    #[test]
+    fn test_extract_form_with_repeated_keys() {
+        let router = Router::new().with_endpoint("/signup", &["post"], signup);
+
+        let body = b"name=Jos%C3%A9+Doe&topic=rust&topic=web%26http".to_vec();
+        let response = post(&router, "application/x-www-form-urlencoded", body);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(
+            response.body(),
+            "José Doe None [\"rust\", \"web&http\"]".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_decode_form_charset() {
+        let router = Router::new().with_endpoint("/signup", &["post"], signup);
+        let content_type = "application/x-www-form-urlencoded; charset=ISO-8859-1";
+
+        let body = b"name=Jos%E9&newsletter=true".to_vec();
+        let response = post(&router, content_type, body);
+        assert_eq!(response.body(), "José Some(true) []".as_bytes());
+
+        let content_type = "application/x-www-form-urlencoded; charset=utf-16";
+        let response = post(&router, content_type, b"name=bob".to_vec());
+        assert_eq!(response.status(), StatusCode::UnsupportedMediaType);
+    }
+
+    #[test]
+    fn test_reject_invalid_form() {
+        let router = Router::new().with_endpoint("/signup", &["post"], signup);
+
+        let response = post(&router, "multipart/form-data", b"name=bob".to_vec());
+        assert_eq!(response.status(), StatusCode::UnsupportedMediaType);
+
+        let body = b"name=bob&newsletter=maybe".to_vec();
+        let response = post(&router, "application/x-www-form-urlencoded", body);
+        assert_eq!(response.status(), StatusCode::UnprocessableEntity);
+
+        let mut request = HttpRequest::new(MethodType::POST, "/signup")
+            .with_header("Content-Type", "application/x-www-form-urlencoded")
+            .with_body("name=a-very-long-name");
+        request.extensions_mut().insert(BodyLimit(8));
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::extract::{FromRequest, Rejection};
+use crate::request::HttpRequest;
+use futures::future::{ready, BoxFuture, FutureExt};
+use std::ops::{Deref, DerefMut};
+
+// Typed value of a request header, parsed from its first occurrence.
+pub trait FromHeader: Sized + Send + 'static {
+    const NAME: &'static str;
+
+    fn from_header(value: &str) -> Option<Self>;
+}
+
+// Header parsed as `T`. Rejects the request with 400 Bad Request when the header
+// is missing or invalid, use `Option<Header<T>>` for the optional ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header<T>(pub T);
+
+impl_deref!(Header);
+
+impl<T: FromHeader> FromRequest for Header<T> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let result = match request.header(T::NAME) {
+            Some(value) => T::from_header(value.trim())
+                .map(Header)
+                .ok_or_else(|| Rejection::bad_request(&format!("invalid {} header", T::NAME))),
+            None => Err(Rejection::bad_request(&format!(
+                "missing {} header",
+                T::NAME
+            ))),
+        };
+        ready(result).boxed()
+    }
+}
+
+macro_rules! string_header {
+    ($($name:ident => $header:expr;)*) => {
+        $(
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct $name(pub String);
+
+            impl FromHeader for $name {
+                const NAME: &'static str = $header;
+
+                fn from_header(value: &str) -> Option<Self> {
+                    Some($name(value.to_string()))
+                }
+            }
+        )*
+    };
+}
+
+string_header! {
+    Accept => "Accept";
+    Authorization => "Authorization";
+    ContentType => "Content-Type";
+    Host => "Host";
+    LastEventId => "Last-Event-ID";
+    Referer => "Referer";
+    UserAgent => "User-Agent";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContentLength(pub u64);
+
+impl FromHeader for ContentLength {
+    const NAME: &'static str = "Content-Length";
+
+    fn from_header(value: &str) -> Option<Self> {
+        value.parse().ok().map(ContentLength)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extract::header::{ContentLength, Header, UserAgent};
+    use crate::extract::FromRequest;
+    use crate::request::HttpRequest;
+    use crate::routing::endpoint::MethodType;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+
+    #[test]
+    fn test_extract_typed_headers() {
+        let mut request = HttpRequest::new(MethodType::GET, "/")
+            .with_header("user-agent", "curl/7.68.0")
+            .with_header("Content-Length", "abc");
+
+        let user_agent = run(
+            Header::<UserAgent>::from_request(&mut request),
+            ProcStack::default(),
+        );
+        assert_eq!(user_agent.unwrap().0, UserAgent("curl/7.68.0".to_string()));
+
+        let length = run(
+            Header::<ContentLength>::from_request(&mut request),
+            ProcStack::default(),
+        );
+        let rejection = length.unwrap_err();
+        assert_eq!(rejection.status(), StatusCode::BadRequest);
+        assert_eq!(rejection.message(), "invalid Content-Length header");
+    }
+}
@@ -0,0 +1,165 @@
+use crate::extract::{read_body, FromRequest, Rejection};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, IntoResponse};
+use crate::status_code::StatusCode;
+use futures::future::{BoxFuture, FutureExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::error::Category;
+use std::ops::{Deref, DerefMut};
+use tracing::error;
+
+// JSON body of the request or the response. As an extractor, it requires the
+// `application/json` content type (or a `+json` suffixed one) and a body under the
+// body limit. Malformed JSON is rejected with 400 Bad Request and a JSON not matching
+// the type with 422 Unprocessable Entity, both with a JSON body describing the error.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl_deref!(Json);
+
+fn is_json(media_type: &str) -> bool {
+    match media_type.split_once('/') {
+        Some(("application", subtype)) => subtype == "json" || subtype.ends_with("+json"),
+        _ => false,
+    }
+}
+
+fn json_rejection(err: serde_json::Error) -> Rejection {
+    let status = match err.classify() {
+        Category::Data => StatusCode::UnprocessableEntity,
+        _ => StatusCode::BadRequest,
+    };
+    let message = format!("invalid JSON body: {}", err);
+    let body = serde_json::json!({
+        "error": message,
+        "line": err.line(),
+        "column": err.column(),
+    });
+    Rejection::new(status, &message).with_response(Json(body).into_response())
+}
+
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Json<T> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        async move {
+            if !request
+                .media_type()
+                .is_some_and(|media_type| is_json(&media_type))
+            {
+                return Err(Rejection::new(
+                    StatusCode::UnsupportedMediaType,
+                    "expected a request with the `application/json` content type",
+                ));
+            }
+            let body = read_body(request).await?;
+            serde_json::from_slice(&body)
+                .map(Json)
+                .map_err(json_rejection)
+        }
+        .boxed()
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> HttpResponse {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => HttpResponse::new(StatusCode::Ok)
+                .with_header("Content-Type", "application/json")
+                .with_body(body),
+            Err(err) => {
+                error!("failed to serialize the JSON response: {}", err);
+                HttpResponse::new(StatusCode::InternalServerError)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extract::{BodyLimit, Json};
+    use crate::middleware::Next;
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    async fn create_user(Json(user): Json<User>) -> (StatusCode, Json<User>) {
+        (StatusCode::Created, Json(user))
+    }
+
+    async fn limit_body(mut request: HttpRequest, next: Next) -> HttpResponse {
+        request.extensions_mut().insert(BodyLimit(16));
+        next.run(request).await
+    }
+
+    fn post(router: &Router, content_type: &str, body: &'static str) -> HttpResponse {
+        let request = HttpRequest::new(MethodType::POST, "/users")
+            .with_header("Content-Type", content_type)
+            .with_body(body);
+        run(router.dispatch(request), ProcStack::default())
+    }
+
+    fn body(response: &HttpResponse) -> &str {
+        std::str::from_utf8(response.body()).unwrap()
+    }
+
+    #[test]
+    fn test_extract_and_respond_json() {
+        let router = Router::new().with_endpoint("/users", &["post"], create_user);
+
+        let response = post(
+            &router,
+            "application/json; charset=utf-8",
+            r#"{"name":"bob","age":42}"#,
+        );
+        assert_eq!(response.status(), StatusCode::Created);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("application/json")
+        );
+        assert_eq!(body(&response), r#"{"name":"bob","age":42}"#);
+    }
+
+    #[test]
+    fn test_reject_invalid_json() {
+        let router = Router::new().with_endpoint("/users", &["post"], create_user);
+
+        let response = post(&router, "text/plain", r#"{"name":"bob","age":42}"#);
+        assert_eq!(response.status(), StatusCode::UnsupportedMediaType);
+
+        let response = post(&router, "application/json", r#"{"name":"bob","age":-1}"#);
+        assert_eq!(response.status(), StatusCode::UnprocessableEntity);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("application/json")
+        );
+        let error: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(error["line"], 1);
+        assert_eq!(error["column"], 22);
+
+        let response = post(&router, "application/problem+json", r#"{"name":"#);
+        assert_eq!(response.status(), StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn test_reject_json_over_limit() {
+        let router = Router::new().with_middleware(limit_body).with_endpoint(
+            "/users",
+            &["post"],
+            create_user,
+        );
+
+        let response = post(&router, "application/json", r#"{"name":"bob","age":42}"#);
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+    }
+}
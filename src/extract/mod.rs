@@ -0,0 +1,462 @@
+use crate::error::BodyError;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, IntoResponse};
+use crate::routing::pattern::MatchedRoute;
+use crate::status_code::StatusCode;
+use de::{from_pairs, from_params};
+use futures::future::{ready, BoxFuture, FutureExt};
+use percent_encoding::percent_decode_str;
+use serde::de::DeserializeOwned;
+use std::any::type_name;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use url::form_urlencoded;
+
+macro_rules! impl_deref {
+    ($name:ident) => {
+        impl<T> Deref for $name<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $name<T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+    };
+}
+
+pub mod cookie;
+mod de;
+mod form;
+pub mod header;
+#[cfg(feature = "json")]
+mod json;
+pub mod multipart;
+
+pub use self::cookie::{CookieJar, PrivateCookieJar, SignedCookieJar};
+pub use form::Form;
+pub use header::{FromHeader, Header};
+#[cfg(feature = "json")]
+pub use json::Json;
+pub use multipart::{Multipart, MultipartLimits};
+
+// Maximum size of the bodies read by the extractors, unless a `BodyLimit` is given.
+pub const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+// Value built from the incoming request, to be taken as an argument by the handlers.
+// Extractors get the request mutably, so the ones consuming a part of it (like the
+// body) can take it out; the following extractors then see it as already consumed.
+pub trait FromRequest: Sized + Send + 'static {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>>;
+}
+
+// Error response returned when an extractor can't build its value from the request.
+// The message is sent as plain text, unless another response is given.
+#[derive(Debug)]
+pub struct Rejection {
+    status: StatusCode,
+    message: String,
+    response: Option<HttpResponse>,
+}
+
+impl Rejection {
+    pub fn new(status: StatusCode, message: &str) -> Self {
+        Rejection {
+            status,
+            message: message.to_string(),
+            response: None,
+        }
+    }
+
+    // Replaces the plain text response, the status of the rejection is kept.
+    pub fn with_response(mut self, response: HttpResponse) -> Self {
+        self.response = Some(response);
+        self
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Rejection::new(StatusCode::BadRequest, message)
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> HttpResponse {
+        match self.response {
+            Some(response) => (self.status, response).into_response(),
+            None => (self.status, self.message).into_response(),
+        }
+    }
+}
+
+// Limits the size of the bodies read by the extractors. It's taken from the request
+// extensions, so a middleware can set it for some routes, then from the application
+// state, and defaults to `DEFAULT_BODY_LIMIT`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyLimit(pub usize);
+
+// Returns the extractor settings of the given type, from the request extensions
+// or the application state.
+pub(crate) fn settings<T: Copy + Send + Sync + 'static>(request: &HttpRequest) -> Option<T> {
+    request
+        .extensions()
+        .get::<T>()
+        .or_else(|| request.state::<T>())
+        .copied()
+}
+
+fn body_limit(request: &HttpRequest) -> usize {
+    settings::<BodyLimit>(request).map_or(DEFAULT_BODY_LIMIT, |limit| limit.0)
+}
+
+// Reads the whole body of the request, rejecting it with 413 Payload Too Large
+// when it's over the limit.
+pub(crate) async fn read_body(request: &mut HttpRequest) -> Result<Vec<u8>, Rejection> {
+    let limit = body_limit(request);
+    let too_large = || {
+        Rejection::new(
+            StatusCode::PayloadTooLarge,
+            &format!("request body is larger than the limit of {} bytes", limit),
+        )
+    };
+    let length = request
+        .header("Content-Length")
+        .and_then(|length| length.trim().parse::<u64>().ok());
+    if length.is_some_and(|length| length > limit as u64) {
+        return Err(too_large());
+    }
+
+    match request.take_body().to_bytes(limit).await {
+        Ok(bytes) => Ok(bytes),
+        Err(BodyError::TooLarge(_)) => Err(too_large()),
+        Err(BodyError::IOError(err)) => Err(Rejection::bad_request(&format!(
+            "failed to read the request body: {}",
+            err
+        ))),
+    }
+}
+
+// The whole body of the request.
+impl FromRequest for Vec<u8> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        read_body(request).boxed()
+    }
+}
+
+// The whole body of the request, rejected with 400 Bad Request if it isn't UTF-8.
+impl FromRequest for String {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        async move {
+            let bytes = read_body(request).await?;
+            String::from_utf8(bytes)
+                .map_err(|_| Rejection::bad_request("request body isn't valid UTF-8"))
+        }
+        .boxed()
+    }
+}
+
+// Gives the request itself to the handler, without its consumed parts.
+impl FromRequest for HttpRequest {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        ready(Ok(request.take())).boxed()
+    }
+}
+
+// Makes the extractor optional, its rejection is turned into `None`.
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        T::from_request(request)
+            .map(|result| Ok(result.ok()))
+            .boxed()
+    }
+}
+
+// Lets the handler deal with the rejection of the extractor itself.
+impl<T: FromRequest> FromRequest for Result<T, Rejection> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        T::from_request(request).map(Ok).boxed()
+    }
+}
+
+// Route parameters, percent-decoded and deserialized into a single value when the
+// route has one parameter (e.g. `Path<u32>`), a tuple taking them in order, or a
+// struct taking them by name. Rejects the request with 400 Bad Request on failure,
+// or 500 Internal Server Error when a value satisfying the constraint of its parameter
+// (e.g. `{slug:[a-z-]+}`) can't be deserialized, as the route and the type disagree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path<T>(pub T);
+
+impl_deref!(Path);
+
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Path<T> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let params: Vec<(String, String)> = request
+            .params()
+            .iter()
+            .map(|(name, value)| {
+                let value = percent_decode_str(value).decode_utf8_lossy();
+                (name.clone(), value.into_owned())
+            })
+            .collect();
+        let constraints = request
+            .extensions()
+            .get::<MatchedRoute>()
+            .map_or(&[][..], |route| route.constraints.as_slice());
+        let result = from_params(&params, constraints).map(Path).map_err(|err| {
+            let message = format!("invalid path parameters: {}", err);
+            if err.is_constraint_mismatch() {
+                Rejection::new(StatusCode::InternalServerError, &message)
+            } else {
+                Rejection::bad_request(&message)
+            }
+        });
+        ready(result).boxed()
+    }
+}
+
+// Query string deserialized into a struct or a map, where repeated keys can be
+// collected into a `Vec`. Rejects the request with 400 Bad Request on failure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query<T>(pub T);
+
+impl_deref!(Query);
+
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Query<T> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let pairs: Vec<(String, String)> =
+            form_urlencoded::parse(request.query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect();
+        let result = from_pairs(&pairs)
+            .map(Query)
+            .map_err(|err| Rejection::bad_request(&format!("invalid query string: {}", err)));
+        ready(result).boxed()
+    }
+}
+
+// Application state registered with `AppBuilder::with_state`. A missing state is
+// a programming error, answered with 500 Internal Server Error.
+#[derive(Debug)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let result = request.app_state().get_shared().map(State).ok_or_else(|| {
+            Rejection::new(
+                StatusCode::InternalServerError,
+                &format!("missing application state of type {}", type_name::<T>()),
+            )
+        });
+        ready(result).boxed()
+    }
+}
+
+// Clone of the request extension of type `T`, usually inserted by a middleware.
+// A missing extension is answered with 500 Internal Server Error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extension<T>(pub T);
+
+impl_deref!(Extension);
+
+impl<T: Clone + Send + Sync + 'static> FromRequest for Extension<T> {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let result = match request.extensions().get::<T>() {
+            Some(value) => Ok(Extension(value.clone())),
+            None => Err(Rejection::new(
+                StatusCode::InternalServerError,
+                &format!("missing request extension of type {}", type_name::<T>()),
+            )),
+        };
+        ready(result).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extensions::AppState;
+    use crate::extract::{Extension, FromRequest, Path, Query, Rejection, State};
+    use crate::middleware::Next;
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use serde::Deserialize;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[derive(Deserialize)]
+    struct Pagination {
+        page: u32,
+        tag: Vec<String>,
+    }
+
+    async fn show_user(Path((team, id)): Path<(String, u32)>) -> String {
+        format!("{} #{}", team, id)
+    }
+
+    async fn list_users(Path(team): Path<String>, Query(pagination): Query<Pagination>) -> String {
+        format!("{} page {} {:?}", team, pagination.page, pagination.tag)
+    }
+
+    async fn echo_path(request: HttpRequest, query: Option<Query<Pagination>>) -> String {
+        format!("{} {}", request.path(), query.is_some())
+    }
+
+    fn dispatch(router: &Router, target: &str) -> HttpResponse {
+        let request = HttpRequest::new(MethodType::GET, target);
+        run(router.dispatch(request), ProcStack::default())
+    }
+
+    #[test]
+    fn test_extract_path_and_query() {
+        let router = Router::new()
+            .with_endpoint("/teams/{team}/users/{id}", &["get"], show_user)
+            .with_endpoint("/teams/{team}/users", &["get"], list_users);
+
+        let response = dispatch(&router, "/teams/core%20team/users/7");
+        assert_eq!(response.body(), b"core team #7");
+
+        let response = dispatch(&router, "/teams/core/users?page=2&tag=a&tag=b%2Bc");
+        assert_eq!(response.body(), b"core page 2 [\"a\", \"b+c\"]");
+    }
+
+    #[test]
+    fn test_reject_invalid_parameters() {
+        let router = Router::new()
+            .with_endpoint("/teams/{team}/users/{id}", &["get"], show_user)
+            .with_endpoint("/teams/{team}/users", &["get"], list_users);
+
+        let response = dispatch(&router, "/teams/core/users/abc");
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(
+            response.body(),
+            &b"invalid path parameters: invalid value \"abc\", expected a non-negative integer"[..]
+        );
+
+        let response = dispatch(&router, "/teams/core/users?tag=a");
+        assert_eq!(response.status(), StatusCode::BadRequest);
+    }
+
+    #[test]
+    fn test_reject_parameters_disagreeing_with_constraints() {
+        async fn show_post(Path(id): Path<u32>) -> String {
+            id.to_string()
+        }
+
+        let router = Router::new()
+            .with_endpoint("/posts/{slug:[a-z-]+}", &["get"], show_post)
+            .with_endpoint("/users/{id:int}", &["get"], show_post);
+
+        let response = dispatch(&router, "/posts/hello-world");
+        assert_eq!(response.status(), StatusCode::InternalServerError);
+        assert_eq!(
+            response.body(),
+            &b"invalid path parameters: value \"hello-world\" satisfies the `[a-z-]+` \
+               constraint of the route but isn't a non-negative integer"[..]
+        );
+
+        let response = dispatch(&router, "/users/-1");
+        assert_eq!(response.status(), StatusCode::InternalServerError);
+        let response = dispatch(&router, "/users/7");
+        assert_eq!(response.body(), b"7");
+    }
+
+    #[test]
+    fn test_optional_extractor_and_request() {
+        let router = Router::new().with_endpoint("/users", &["get"], echo_path);
+
+        let response = dispatch(&router, "/users?page=x");
+        assert_eq!(response.body(), b"/users false");
+        let response = dispatch(&router, "/users?page=1&tag=a");
+        assert_eq!(response.body(), b"/users true");
+    }
+
+    #[derive(Clone)]
+    struct CurrentUser(String);
+
+    async fn count_visits(
+        State(counter): State<AtomicUsize>,
+        Extension(user): Extension<CurrentUser>,
+    ) -> String {
+        let visits = counter.fetch_add(1, Ordering::SeqCst) + 1;
+        format!("{} visit #{}", user.0, visits)
+    }
+
+    async fn authenticate(mut request: HttpRequest, next: Next) -> HttpResponse {
+        if let Some(user) = request.header("X-User").map(str::to_string) {
+            request.extensions_mut().insert(CurrentUser(user));
+        }
+        next.run(request).await
+    }
+
+    #[test]
+    fn test_extract_state_and_extension() {
+        let router =
+            Router::new()
+                .with_middleware(authenticate)
+                .with_endpoint("/", &["get"], count_visits);
+        let mut state = AppState::new();
+        state.insert(AtomicUsize::new(0));
+        let state = Arc::new(state);
+
+        for expected in &["alice visit #1", "alice visit #2"] {
+            let mut request = HttpRequest::new(MethodType::GET, "/").with_header("X-User", "alice");
+            request.set_state(state.clone());
+            let response = run(router.dispatch(request), ProcStack::default());
+            assert_eq!(response.body(), expected.as_bytes());
+        }
+
+        // Neither the state nor the extension are available
+        let response = dispatch(&router, "/");
+        assert_eq!(response.status(), StatusCode::InternalServerError);
+    }
+
+    #[test]
+    fn test_rejection_can_be_handled() {
+        let mut request = HttpRequest::new(MethodType::GET, "/");
+        let result = run(
+            Result::<Path<u32>, Rejection>::from_request(&mut request),
+            ProcStack::default(),
+        );
+        assert_eq!(
+            result.unwrap().unwrap_err().status(),
+            StatusCode::BadRequest
+        );
+    }
+}
@@ -0,0 +1,650 @@
+use crate::body::Body;
+use crate::error::MultipartError;
+use crate::extract::{settings, FromRequest, Rejection};
+use crate::headers::Headers;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, IntoResponse};
+use crate::status_code::StatusCode;
+use futures::future::{ready, BoxFuture, FutureExt};
+use futures::stream::{Stream, StreamExt};
+use httparse::{Status, EMPTY_HEADER};
+use percent_encoding::percent_decode_str;
+use std::io::Write;
+use std::pin::Pin;
+use std::result;
+use std::task::{Context, Poll};
+use tempfile::NamedTempFile;
+
+type Result<T> = result::Result<T, MultipartError>;
+
+// Maximum size of the headers of a part.
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+
+// Size limits of the multipart bodies, taken from the request extensions or the
+// application state like the `BodyLimit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultipartLimits {
+    // Maximum size of the content of a field
+    pub field_size: usize,
+    // Maximum size of the whole body
+    pub total_size: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        MultipartLimits {
+            field_size: 16 * 1024 * 1024,
+            total_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    // Before the first boundary
+    Preamble,
+    // Right after a boundary, before the headers of the next part or the final `--`
+    Boundary,
+    // Reading the content of a field
+    Field,
+    Done,
+}
+
+// Streaming parser of a `multipart/form-data` body. The fields are read one after
+// the other as the body comes from the client, so a field must be consumed before
+// asking for the next one (any unread content is skipped).
+pub struct Multipart {
+    body: Body,
+    // The `\r\n--boundary` delimiter preceding every boundary but the first one
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: State,
+    limits: MultipartLimits,
+    total_size: usize,
+    field_size: usize,
+    field_name: String,
+}
+
+impl Multipart {
+    pub fn new(body: Body, boundary: &str, limits: MultipartLimits) -> Self {
+        Multipart {
+            body,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            buffer: Vec::new(),
+            state: State::Preamble,
+            limits,
+            total_size: 0,
+            field_size: 0,
+            field_name: String::new(),
+        }
+    }
+
+    // Returns the next field, or `None` after the last one.
+    pub async fn next_field(&mut self) -> Result<Option<Field<'_>>> {
+        loop {
+            match self.state {
+                State::Preamble => self.skip_preamble()?,
+                State::Field => while self.read_field_chunk()?.is_some() {},
+                State::Boundary => {
+                    if !self.read_boundary_end()? {
+                        self.state = State::Done;
+                        return Ok(None);
+                    }
+                    let headers = self.read_headers()?;
+                    return Ok(Some(self.start_field(headers)));
+                }
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    // Reads another chunk of the body into the buffer, returning false at its end.
+    fn fill(&mut self) -> Result<bool> {
+        match self.body.read_chunk() {
+            Some(chunk) => {
+                let chunk = chunk?;
+                self.total_size += chunk.len();
+                if self.total_size > self.limits.total_size {
+                    return Err(MultipartError::TooLarge(self.limits.total_size));
+                }
+                self.buffer.extend_from_slice(&chunk);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn fill_or_fail(&mut self) -> Result<()> {
+        if self.fill()? {
+            Ok(())
+        } else {
+            Err(MultipartError::Incomplete)
+        }
+    }
+
+    fn skip_preamble(&mut self) -> Result<()> {
+        // The first boundary may start the body, without the preceding line break
+        let dash_boundary = self.delimiter[2..].to_vec();
+        loop {
+            if let Some(index) = find(&self.buffer, &dash_boundary) {
+                let end = index + dash_boundary.len();
+                self.buffer.drain(..end);
+                self.state = State::Boundary;
+                return Ok(());
+            }
+            let keep = self.buffer.len().min(dash_boundary.len() - 1);
+            self.buffer.drain(..self.buffer.len() - keep);
+            self.fill_or_fail()?;
+        }
+    }
+
+    // Reads the end of the boundary line, returning false for the closing boundary.
+    fn read_boundary_end(&mut self) -> Result<bool> {
+        loop {
+            if self.buffer.starts_with(b"--") {
+                return Ok(false);
+            }
+            if let Some(index) = find(&self.buffer, b"\r\n") {
+                // Only the transport padding may follow the boundary
+                if !self.buffer[..index]
+                    .iter()
+                    .all(|&byte| byte == b' ' || byte == b'\t')
+                {
+                    return Err(MultipartError::Invalid(
+                        "unexpected data after the boundary".to_string(),
+                    ));
+                }
+                self.buffer.drain(..index + 2);
+                return Ok(true);
+            }
+            if self.buffer.len() > MAX_HEADERS_SIZE {
+                return Err(MultipartError::Invalid(
+                    "boundary line is too long".to_string(),
+                ));
+            }
+            self.fill_or_fail()?;
+        }
+    }
+
+    fn read_headers(&mut self) -> Result<Headers> {
+        loop {
+            let mut parsed = [EMPTY_HEADER; 32];
+            match httparse::parse_headers(&self.buffer, &mut parsed) {
+                Ok(Status::Complete((length, parsed))) => {
+                    let mut headers = Headers::new();
+                    for header in parsed {
+                        headers.append(header.name, &String::from_utf8_lossy(header.value));
+                    }
+                    self.buffer.drain(..length);
+                    return Ok(headers);
+                }
+                Ok(Status::Partial) if self.buffer.len() <= MAX_HEADERS_SIZE => {
+                    self.fill_or_fail()?
+                }
+                Ok(Status::Partial) => {
+                    return Err(MultipartError::Invalid(
+                        "part headers are too large".to_string(),
+                    ))
+                }
+                Err(err) => return Err(MultipartError::Invalid(err.to_string())),
+            }
+        }
+    }
+
+    fn start_field(&mut self, headers: Headers) -> Field<'_> {
+        let disposition = headers.get("Content-Disposition").unwrap_or("");
+        let name = disposition_param(disposition, "name");
+        let file_name = disposition_param(disposition, "filename*")
+            .and_then(|value| decode_ext_value(&value))
+            .or_else(|| disposition_param(disposition, "filename"));
+        let content_type = headers.get("Content-Type").map(str::to_string);
+
+        self.state = State::Field;
+        self.field_size = 0;
+        self.field_name = name.clone().unwrap_or_default();
+        Field {
+            multipart: self,
+            headers,
+            name,
+            file_name,
+            content_type,
+        }
+    }
+
+    // Reads the next chunk of the current field, returning `None` at its end.
+    fn read_field_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.state != State::Field {
+            return Ok(None);
+        }
+        loop {
+            let chunk = match find(&self.buffer, &self.delimiter) {
+                Some(index) => {
+                    let chunk: Vec<u8> = self.buffer.drain(..index).collect();
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = State::Boundary;
+                    chunk
+                }
+                None => {
+                    // Keep what could be the beginning of the delimiter
+                    let keep = self.buffer.len().min(self.delimiter.len() - 1);
+                    let chunk: Vec<u8> = self.buffer.drain(..self.buffer.len() - keep).collect();
+                    if chunk.is_empty() {
+                        self.fill_or_fail()?;
+                        continue;
+                    }
+                    chunk
+                }
+            };
+
+            self.field_size += chunk.len();
+            if self.field_size > self.limits.field_size {
+                return Err(MultipartError::FieldTooLarge {
+                    name: self.field_name.clone(),
+                    limit: self.limits.field_size,
+                });
+            }
+            if chunk.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(chunk));
+        }
+    }
+}
+
+impl FromRequest for Multipart {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, result::Result<Self, Rejection>> {
+        let limits = settings::<MultipartLimits>(request).unwrap_or_default();
+        if request.media_type().as_deref() != Some("multipart/form-data") {
+            return ready(Err(Rejection::new(
+                StatusCode::UnsupportedMediaType,
+                "expected a request with the `multipart/form-data` content type",
+            )))
+            .boxed();
+        }
+        let boundary = request
+            .header("Content-Type")
+            .and_then(|content_type| content_type_param(content_type, "boundary"));
+        let boundary = match boundary {
+            Some(boundary) if !boundary.is_empty() && boundary.len() <= 70 => boundary,
+            _ => {
+                return ready(Err(Rejection::bad_request(
+                    "missing or invalid boundary of the multipart body",
+                )))
+                .boxed()
+            }
+        };
+        let length = request
+            .header("Content-Length")
+            .and_then(|length| length.trim().parse::<u64>().ok());
+        if length.is_some_and(|length| length > limits.total_size as u64) {
+            let rejection = MultipartError::TooLarge(limits.total_size).into_rejection();
+            return ready(Err(rejection)).boxed();
+        }
+
+        ready(Ok(Multipart::new(request.take_body(), &boundary, limits))).boxed()
+    }
+}
+
+// Field of the multipart body, streaming its content in chunks.
+pub struct Field<'a> {
+    multipart: &'a mut Multipart,
+    headers: Headers,
+    name: Option<String>,
+    file_name: Option<String>,
+    content_type: Option<String>,
+}
+
+impl<'a> Field<'a> {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    // Returns the next chunk of the content, or `None` once it's complete.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        self.next().await.transpose()
+    }
+
+    pub async fn bytes(mut self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(bytes)
+    }
+
+    pub async fn text(self) -> Result<String> {
+        String::from_utf8(self.bytes().await?)
+            .map_err(|_| MultipartError::Invalid("field isn't valid UTF-8".to_string()))
+    }
+
+    // Reads the content in memory while it's under the threshold, then moves it
+    // to a temporary file, deleted once the returned value is dropped.
+    pub async fn spool(mut self, threshold: usize) -> Result<Spooled> {
+        let mut len = 0;
+        let mut data = SpooledData::Memory(Vec::new());
+        while let Some(chunk) = self.chunk().await? {
+            len += chunk.len() as u64;
+            data = match data {
+                SpooledData::Memory(mut bytes) if bytes.len() + chunk.len() > threshold => {
+                    let mut file = NamedTempFile::new()?;
+                    file.write_all(&bytes)?;
+                    file.write_all(&chunk)?;
+                    bytes.clear();
+                    SpooledData::File(file)
+                }
+                SpooledData::Memory(mut bytes) => {
+                    bytes.extend_from_slice(&chunk);
+                    SpooledData::Memory(bytes)
+                }
+                SpooledData::File(mut file) => {
+                    file.write_all(&chunk)?;
+                    SpooledData::File(file)
+                }
+            };
+        }
+        if let SpooledData::File(file) = &mut data {
+            file.flush()?;
+        }
+        Ok(Spooled { len, data })
+    }
+}
+
+impl Stream for Field<'_> {
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.get_mut().multipart.read_field_chunk().transpose())
+    }
+}
+
+// Content of a field read by `Field::spool`.
+#[derive(Debug)]
+pub struct Spooled {
+    len: u64,
+    data: SpooledData,
+}
+
+#[derive(Debug)]
+pub enum SpooledData {
+    Memory(Vec<u8>),
+    File(NamedTempFile),
+}
+
+impl Spooled {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn data(&self) -> &SpooledData {
+        &self.data
+    }
+
+    pub fn into_data(self) -> SpooledData {
+        self.data
+    }
+}
+
+impl MultipartError {
+    fn into_rejection(self) -> Rejection {
+        let status = match self {
+            MultipartError::FieldTooLarge { .. } | MultipartError::TooLarge(_) => {
+                StatusCode::PayloadTooLarge
+            }
+            _ => StatusCode::BadRequest,
+        };
+        Rejection::new(status, &self.to_string())
+    }
+}
+
+// Answers with 413 Payload Too Large for the limits, otherwise 400 Bad Request.
+impl IntoResponse for MultipartError {
+    fn into_response(self) -> HttpResponse {
+        self.into_rejection().into_response()
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+// Returns the value of the parameter of a `Content-Type` like header.
+fn content_type_param(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+// Returns the parameter of the `Content-Disposition` header, where quoted values
+// may contain semicolons and escaped characters.
+fn disposition_param(value: &str, name: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        let (key, after_key) = rest.split_once('=')?;
+        let after_key = after_key.trim_start();
+        let (param, after_value) = if let Some(quoted) = after_key.strip_prefix('"') {
+            let mut param = String::new();
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((index, c)) = chars.next() {
+                match c {
+                    '\\' => param.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = index + 1;
+                        break;
+                    }
+                    c => param.push(c),
+                }
+            }
+            let after = &quoted[end..];
+            (param, after.split_once(';').map_or("", |(_, after)| after))
+        } else {
+            match after_key.split_once(';') {
+                Some((param, after)) => (param.trim().to_string(), after),
+                None => (after_key.trim().to_string(), ""),
+            }
+        };
+        if key.trim().eq_ignore_ascii_case(name) {
+            return Some(param);
+        }
+        if after_value.is_empty() {
+            return None;
+        }
+        rest = after_value;
+    }
+}
+
+// Decodes the RFC 5987 value of the `filename*` parameter (e.g. `UTF-8''na%C3%AFve.txt`).
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+    if !charset.eq_ignore_ascii_case("utf-8") {
+        return None;
+    }
+    percent_decode_str(encoded)
+        .decode_utf8()
+        .ok()
+        .map(|decoded| decoded.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::body::Body;
+    use crate::error::MultipartError;
+    use crate::extract::multipart::{Multipart, MultipartLimits, SpooledData};
+    use crate::request::HttpRequest;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use std::io::{self, Read};
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        Holidays\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"photo\"; filename=\"beach; 1.jpg\"\r\n\
+        Content-Type: image/jpeg\r\n\r\n\
+        \xff\xd8\r\n--Xy\xff\xd9\r\n--XyZ--\r\nepilogue";
+
+    // Reader returning a single byte at a time, to split the boundaries
+    struct ByteReader(&'static [u8]);
+
+    impl Read for ByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((byte, rest)) if !buf.is_empty() => {
+                    buf[0] = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    type Fields = Vec<(Option<String>, Option<String>, Option<String>, Vec<u8>)>;
+
+    async fn read_fields(mut multipart: Multipart) -> Result<Fields, MultipartError> {
+        let mut fields = Vec::new();
+        while let Some(field) = multipart.next_field().await? {
+            let name = field.name().map(str::to_string);
+            let file_name = field.file_name().map(str::to_string);
+            let content_type = field.content_type().map(str::to_string);
+            fields.push((name, file_name, content_type, field.bytes().await?));
+        }
+        Ok(fields)
+    }
+
+    fn parse(body: Body, limits: MultipartLimits) -> Result<Fields, MultipartError> {
+        run(
+            read_fields(Multipart::new(body, "XyZ", limits)),
+            ProcStack::default(),
+        )
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        for body in [
+            Body::from(BODY.to_vec()),
+            Body::from_reader(ByteReader(BODY)),
+        ] {
+            let fields = parse(body, MultipartLimits::default()).unwrap();
+
+            assert_eq!(fields.len(), 2);
+            assert_eq!(fields[0].0.as_deref(), Some("title"));
+            assert_eq!(fields[0].3, b"Holidays");
+            assert_eq!(fields[1].1.as_deref(), Some("beach; 1.jpg"));
+            assert_eq!(fields[1].2.as_deref(), Some("image/jpeg"));
+            assert_eq!(fields[1].3, b"\xff\xd8\r\n--Xy\xff\xd9");
+        }
+    }
+
+    #[test]
+    fn test_enforce_limits() {
+        let limits = MultipartLimits {
+            field_size: 6,
+            total_size: 1024,
+        };
+        let result = parse(Body::from(BODY.to_vec()), limits);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "the \"title\" field is larger than the limit of 6 bytes"
+        );
+
+        let limits = MultipartLimits {
+            field_size: 1024,
+            total_size: 64,
+        };
+        let result = parse(Body::from(BODY.to_vec()), limits);
+        assert!(matches!(result, Err(MultipartError::TooLarge(64))));
+
+        let result = parse(Body::from(BODY[..60].to_vec()), MultipartLimits::default());
+        assert!(matches!(result, Err(MultipartError::Incomplete)));
+    }
+
+    #[test]
+    fn test_spool_large_field_to_file() {
+        let spooled = run(
+            async {
+                let mut multipart =
+                    Multipart::new(Body::from(BODY.to_vec()), "XyZ", MultipartLimits::default());
+                let title = multipart.next_field().await?.unwrap().spool(64).await?;
+                let photo = multipart.next_field().await?.unwrap().spool(4).await?;
+                Ok::<_, MultipartError>((title, photo))
+            },
+            ProcStack::default(),
+        );
+        let (title, photo) = spooled.unwrap();
+
+        assert!(matches!(title.data(), SpooledData::Memory(bytes) if bytes == b"Holidays"));
+        assert_eq!(photo.len(), 10);
+        match photo.into_data() {
+            SpooledData::File(file) => {
+                let content = std::fs::read(file.path()).unwrap();
+                assert_eq!(content, b"\xff\xd8\r\n--Xy\xff\xd9");
+            }
+            SpooledData::Memory(_) => panic!("expected a spooled file"),
+        }
+    }
+
+    async fn upload(mut multipart: Multipart) -> Result<String, MultipartError> {
+        let mut names = Vec::new();
+        while let Some(field) = multipart.next_field().await? {
+            names.push(field.file_name().or(field.name()).unwrap_or("").to_string());
+        }
+        Ok(names.join(","))
+    }
+
+    #[test]
+    fn test_extract_multipart() {
+        let router = Router::new().with_endpoint("/upload", &["post"], upload);
+        let request = |content_type: &str| {
+            HttpRequest::new(MethodType::POST, "/upload")
+                .with_header("Content-Type", content_type)
+                .with_body(BODY.to_vec())
+        };
+
+        let response = run(
+            router.dispatch(request("multipart/form-data; boundary=\"XyZ\"")),
+            ProcStack::default(),
+        );
+        assert_eq!(response.body(), b"title,beach; 1.jpg");
+
+        let response = run(
+            router.dispatch(request("multipart/form-data")),
+            ProcStack::default(),
+        );
+        assert_eq!(response.status(), StatusCode::BadRequest);
+
+        let response = run(
+            router.dispatch(request("multipart/form-data; boundary=Other")),
+            ProcStack::default(),
+        );
+        assert_eq!(response.status(), StatusCode::BadRequest);
+    }
+}
@@ -0,0 +1,526 @@
+use crate::body::Body;
+use crate::conditional::{ETag, Preconditions};
+use crate::handler::Handler;
+use crate::range::{self, RangeRequest, Ranges};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, IntoResponse};
+use crate::routing::pattern::MatchedRoute;
+use crate::status_code::StatusCode;
+use futures::future::{BoxFuture, FutureExt};
+use percent_encoding::percent_decode_str;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+// Content types of the common file extensions, the others are sent as
+// `application/octet-stream`.
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("xml", "application/xml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+// Guesses the content type of the file from its extension.
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    extension
+        .and_then(|extension| {
+            CONTENT_TYPES
+                .iter()
+                .find(|(known, _)| *known == extension)
+                .map(|(_, content_type)| *content_type)
+        })
+        .unwrap_or("application/octet-stream")
+}
+
+fn error_response(err: io::Error) -> HttpResponse {
+    match err.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::IsADirectory => {
+            HttpResponse::new(StatusCode::NotFound)
+        }
+        ErrorKind::PermissionDenied => HttpResponse::new(StatusCode::Forbidden),
+        _ => HttpResponse::new(StatusCode::InternalServerError),
+    }
+}
+
+// Returns the response streaming the file, or the requested ranges of it, which are
+// read chunk by chunk as the client receives them. The conditional requests are
+// answered with the entity tag made of the size and modification time of the file.
+fn open_file(path: &Path, content_type: &str, request: &HttpRequest) -> io::Result<HttpResponse> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if metadata.is_dir() {
+        return Err(io::Error::from(ErrorKind::IsADirectory));
+    }
+    let length = metadata.len();
+    let mut response = HttpResponse::new(StatusCode::Ok)
+        .with_header("Content-Type", content_type)
+        .with_header("Content-Length", &length.to_string())
+        .with_header("Accept-Ranges", "bytes");
+    if let Ok(modified) = metadata.modified() {
+        let nanos = modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos());
+        response = response
+            .with_etag(&ETag::strong(&format!("{:x}-{:x}", nanos, length)))
+            .with_last_modified(modified);
+    }
+
+    let response = Preconditions::new(request).apply(response);
+    if response.status() != StatusCode::Ok {
+        return Ok(response);
+    }
+    match RangeRequest::from_request(request).ranges(&response, length) {
+        Ranges::Full => Ok(response.with_stream(Body::from_reader(file))),
+        Ranges::Unsatisfiable => Ok(range::unsatisfiable_response(response, length)),
+        Ranges::Partial(ranges) => {
+            // Every part has its own handle, as the clones of a file share their position
+            let read_part = |part: &Range<u64>| {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(part.start))?;
+                Ok(Box::new(file.take(part.end - part.start)) as Box<dyn Read + Send>)
+            };
+            range::partial_response(response, &ranges, length, true, read_part)
+        }
+    }
+}
+
+// Endpoint serving a single file, e.g. `Router::new().with_endpoint("/favicon.ico",
+// &["get"], ServeFile::new("assets/favicon.ico"))`.
+#[derive(Debug, Clone)]
+pub struct ServeFile {
+    path: PathBuf,
+    content_type: Option<String>,
+}
+
+impl ServeFile {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        ServeFile {
+            path: path.as_ref().to_path_buf(),
+            content_type: None,
+        }
+    }
+
+    // Replaces the content type guessed from the extension.
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    fn serve(&self, request: &HttpRequest) -> HttpResponse {
+        let content_type = match &self.content_type {
+            Some(content_type) => content_type,
+            None => content_type(&self.path),
+        };
+        open_file(&self.path, content_type, request).unwrap_or_else(error_response)
+    }
+}
+
+impl Handler<()> for ServeFile {
+    fn call(self: Arc<Self>, request: HttpRequest) -> BoxFuture<'static, HttpResponse> {
+        async move { self.serve(&request) }.boxed()
+    }
+}
+
+// Endpoint serving the files of a directory, at the path given by the wildcard
+// parameter of the route, or the parameter set with `param`:
+//
+//     Router::new().with_endpoint("/assets/{*path}", &["get"], ServeDir::new("dist"))
+//
+// Directories are served with their `index.html` file, and the paths trying to get
+// out of the directory are rejected with 400 Bad Request. Files that don't exist are
+// answered with 404 Not Found, unless a fallback file is given. Both endpoints
+// answer the range and conditional requests.
+#[derive(Debug, Clone)]
+pub struct ServeDir {
+    root: PathBuf,
+    // Route parameter holding the path of the file, the route's wildcard by default
+    param: Option<String>,
+    index: Option<String>,
+    fallback: Option<ServeFile>,
+}
+
+impl ServeDir {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        ServeDir {
+            root: root.as_ref().to_path_buf(),
+            param: None,
+            index: Some("index.html".to_string()),
+            fallback: None,
+        }
+    }
+
+    // Takes the path of the files from the given route parameter instead of the
+    // wildcard, e.g. for `/{file}` routes.
+    pub fn param(mut self, name: &str) -> Self {
+        self.param = Some(name.to_string());
+        self
+    }
+
+    // Replaces the name of the file served for the directories.
+    pub fn index_file(mut self, name: &str) -> Self {
+        self.index = Some(name.to_string());
+        self
+    }
+
+    // Answers the requests for directories with 404 Not Found.
+    pub fn without_index(mut self) -> Self {
+        self.index = None;
+        self
+    }
+
+    // Serves the file for the paths that don't exist, like the `index.html` of the
+    // single-page applications routing on the client side.
+    pub fn fallback<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.fallback = Some(ServeFile::new(path));
+        self
+    }
+
+    fn serve(&self, request: &HttpRequest) -> HttpResponse {
+        // Omitted wildcards serve the root of the directory
+        let name = match &self.param {
+            Some(name) => Some(name.as_str()),
+            None => request
+                .extensions()
+                .get::<MatchedRoute>()
+                .and_then(|route| route.wildcard.as_deref()),
+        };
+        let requested = name.and_then(|name| request.param(name)).unwrap_or("");
+        let mut path = match resolve(&self.root, requested) {
+            Some(path) => path,
+            None => return (StatusCode::BadRequest, "invalid path").into_response(),
+        };
+        if path.is_dir() {
+            // Relative links of the index are resolved from the directory
+            if !request.path().ends_with('/') {
+                return redirect_to_directory(request);
+            }
+            match &self.index {
+                Some(index) => path.push(index),
+                None => return HttpResponse::new(StatusCode::NotFound),
+            }
+        }
+
+        match open_file(&path, content_type(&path), request) {
+            Ok(response) => response,
+            Err(err) => match (&self.fallback, err.kind()) {
+                (Some(fallback), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                    fallback.serve(request)
+                }
+                _ => error_response(err),
+            },
+        }
+    }
+}
+
+impl Handler<()> for ServeDir {
+    fn call(self: Arc<Self>, request: HttpRequest) -> BoxFuture<'static, HttpResponse> {
+        async move { self.serve(&request) }.boxed()
+    }
+}
+
+// Joins the percent-decoded path to the root, failing on the segments that could
+// lead out of it (e.g. `..` or an absolute path on Windows).
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let mut resolved = root.to_path_buf();
+    for segment in path.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        let mut components = Path::new(segment).components();
+        let normal =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        if !normal || segment.contains(['\\', '\0']) {
+            return None;
+        }
+        resolved.push(segment);
+    }
+    Some(resolved)
+}
+
+fn redirect_to_directory(request: &HttpRequest) -> HttpResponse {
+    let location = match request.query() {
+        Some(query) => format!("{}/?{}", request.path(), query),
+        None => format!("{}/", request.path()),
+    };
+    HttpResponse::new(StatusCode::PermanentRedirect).with_header("Location", &location)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::{ServeDir, ServeFile};
+    use crate::request::HttpRequest;
+    use crate::response::{HttpResponse, ResponseBody};
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::routing::guard::Host;
+    use crate::routing::route::Route;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use futures::stream::StreamExt;
+    use lightproc::prelude::ProcStack;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn site() -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("docs/guide")).unwrap();
+        fs::write(dir.path().join("index.html"), "<h1>home</h1>").unwrap();
+        fs::write(dir.path().join("app.js"), "run()").unwrap();
+        fs::write(dir.path().join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        fs::write(dir.path().join("docs/guide/intro.md"), "# Intro").unwrap();
+        dir
+    }
+
+    fn read_body(response: &mut HttpResponse) -> Vec<u8> {
+        match response.take_body() {
+            ResponseBody::Bytes(bytes) => bytes,
+            ResponseBody::Stream(stream) => {
+                let chunks = run(stream.collect::<Vec<_>>(), ProcStack::default());
+                chunks.into_iter().flat_map(Result::unwrap).collect()
+            }
+        }
+    }
+
+    fn get(router: &Router, path: &str) -> (HttpResponse, Vec<u8>) {
+        let request = HttpRequest::new(MethodType::GET, path);
+        let mut response = run(router.dispatch(request), ProcStack::default());
+        let body = read_body(&mut response);
+        (response, body)
+    }
+
+    #[test]
+    fn test_serve_files_with_content_type() {
+        let dir = site();
+        let router =
+            Router::new().with_endpoint("/static/{*path}", &["get"], ServeDir::new(dir.path()));
+
+        let (response, body) = get(&router, "/static/app.js");
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(response.headers().get("Content-Length"), Some("5"));
+        assert_eq!(body, b"run()");
+
+        let (response, body) = get(&router, "/static/docs/guide/intro%2Emd");
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/markdown; charset=utf-8")
+        );
+        assert_eq!(body, b"# Intro");
+
+        let (response, _) = get(&router, "/static/missing.css");
+        assert_eq!(response.status(), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_serve_directory_index() {
+        let dir = site();
+        let router =
+            Router::new().with_endpoint("/static/{*path}", &["get"], ServeDir::new(dir.path()));
+
+        let (_, body) = get(&router, "/static/");
+        assert_eq!(body, b"<h1>home</h1>");
+        let (_, body) = get(&router, "/static/docs/");
+        assert_eq!(body, b"<h1>docs</h1>");
+
+        let (response, _) = get(&router, "/static/docs?lang=en");
+        assert_eq!(response.status(), StatusCode::PermanentRedirect);
+        assert_eq!(
+            response.headers().get("Location"),
+            Some("/static/docs/?lang=en")
+        );
+
+        let (response, _) = get(&router, "/static/docs/guide/");
+        assert_eq!(response.status(), StatusCode::NotFound);
+    }
+
+    #[test]
+    fn test_serve_path_from_wildcard() {
+        let dir = site();
+        let host = Host::new("{tenant}.example.com").unwrap();
+        let router = Router::new()
+            .with_route(
+                Route::new("/static/{*path}", &["get"], ServeDir::new(dir.path())).guard(host),
+            )
+            .with_endpoint(
+                "/{tenant}/assets/{*path}",
+                &["get"],
+                ServeDir::new(dir.path()),
+            )
+            .with_endpoint(
+                "/files/{file}",
+                &["get"],
+                ServeDir::new(dir.path()).param("file"),
+            );
+
+        // The parameters captured by the guards come after the wildcard
+        let request = HttpRequest::new(MethodType::GET, "/static/app.js")
+            .with_header("Host", "acme.example.com");
+        let mut response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(read_body(&mut response), b"run()");
+
+        let (response, body) = get(&router, "/acme/assets/");
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(body, b"<h1>home</h1>");
+        let (response, _) = get(&router, "/acme/assets");
+        assert_eq!(response.status(), StatusCode::PermanentRedirect);
+        let (_, body) = get(&router, "/acme/assets/docs/");
+        assert_eq!(body, b"<h1>docs</h1>");
+
+        let (_, body) = get(&router, "/files/app.js");
+        assert_eq!(body, b"run()");
+    }
+
+    #[test]
+    fn test_reject_path_traversal() {
+        let dir = site();
+        let root = dir.path().join("docs");
+        let router = Router::new().with_endpoint("/static/{*path}", &["get"], ServeDir::new(root));
+
+        for path in [
+            "/static/../index.html",
+            "/static/%2E%2E/index.html",
+            "/static/guide/..%2F..%2Findex.html",
+            "/static/..%5Cindex.html",
+        ] {
+            let (response, _) = get(&router, path);
+            assert_eq!(response.status(), StatusCode::BadRequest, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_single_page_fallback() {
+        let dir = site();
+        let serve_dir = ServeDir::new(dir.path()).fallback(dir.path().join("index.html"));
+        let router = Router::new().with_endpoint("/{*path}", &["get"], serve_dir);
+
+        let (response, body) = get(&router, "/users/42");
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(body, b"<h1>home</h1>");
+        let (_, body) = get(&router, "/app.js");
+        assert_eq!(body, b"run()");
+    }
+
+    #[test]
+    fn test_serve_file_ranges() {
+        let dir = site();
+        let router = Router::new().with_endpoint(
+            "/intro",
+            &["get"],
+            ServeFile::new(dir.path().join("docs/guide/intro.md")),
+        );
+
+        let request = HttpRequest::new(MethodType::GET, "/intro").with_header("Range", "bytes=2-");
+        let mut response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.status(), StatusCode::PartialContent);
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes 2-6/7"));
+        assert_eq!(response.headers().get("Content-Length"), Some("5"));
+        assert_eq!(read_body(&mut response), b"Intro");
+
+        let request =
+            HttpRequest::new(MethodType::GET, "/intro").with_header("Range", "bytes=0-0,-1");
+        let mut response = run(router.dispatch(request), ProcStack::default());
+        let body = String::from_utf8(read_body(&mut response)).unwrap();
+        assert!(body.contains("Content-Range: bytes 0-0/7\r\n\r\n#\r\n"));
+        assert!(body.contains("Content-Range: bytes 6-6/7\r\n\r\no\r\n"));
+    }
+
+    #[test]
+    fn test_conditional_requests() {
+        let dir = site();
+        let router =
+            Router::new().with_endpoint("/static/{*path}", &["get"], ServeDir::new(dir.path()));
+        let send = |header: &str, value: &str| {
+            let request =
+                HttpRequest::new(MethodType::GET, "/static/app.js").with_header(header, value);
+            run(router.dispatch(request), ProcStack::default())
+        };
+
+        let (response, _) = get(&router, "/static/app.js");
+        let etag = response.headers().get("ETag").unwrap().to_string();
+        let last_modified = response.headers().get("Last-Modified").unwrap().to_string();
+        assert!(etag.starts_with('"'));
+
+        let response = send("If-None-Match", &etag);
+        assert_eq!(response.status(), StatusCode::NotModified);
+        assert_eq!(response.headers().get("ETag"), Some(etag.as_str()));
+        assert!(!response.is_stream());
+        let response = send("If-Modified-Since", &last_modified);
+        assert_eq!(response.status(), StatusCode::NotModified);
+        let response = send("If-Match", "\"stale\"");
+        assert_eq!(response.status(), StatusCode::PreconditionFailed);
+
+        let request = HttpRequest::new(MethodType::GET, "/static/app.js")
+            .with_header("Range", "bytes=0-2")
+            .with_header("If-Range", &etag);
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.status(), StatusCode::PartialContent);
+    }
+
+    #[test]
+    fn test_serve_file() {
+        let dir = site();
+        let serve_file = ServeFile::new(dir.path().join("docs/guide/intro.md"))
+            .content_type("text/plain; charset=utf-8");
+        let router = Router::new()
+            .with_endpoint("/intro", &["get"], serve_file)
+            .with_endpoint(
+                "/missing",
+                &["get"],
+                ServeFile::new(dir.path().join("missing")),
+            );
+
+        let (response, body) = get(&router, "/intro");
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(body, b"# Intro");
+        let (response, _) = get(&router, "/missing");
+        assert_eq!(response.status(), StatusCode::NotFound);
+    }
+}
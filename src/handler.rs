@@ -0,0 +1,50 @@
+use crate::extract::FromRequest;
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, IntoResponse};
+use futures::future::{BoxFuture, FutureExt};
+use std::future::Future;
+use std::sync::Arc;
+
+// Async function usable as an endpoint handler. It's implemented for the functions
+// taking up to eight extractors (e.g. `Path<T>`, `Query<T>` or the request itself),
+// which are run in the order of the arguments. When one of them fails, its
+// rejection is sent as the response and the function isn't called.
+pub trait Handler<Args>: Send + Sync + 'static {
+    fn call(self: Arc<Self>, request: HttpRequest) -> BoxFuture<'static, HttpResponse>;
+}
+
+macro_rules! impl_handler {
+    ($($arg:ident),*) => {
+        impl<F, Fut, R, $($arg,)*> Handler<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = R> + Send + 'static,
+            R: IntoResponse + 'static,
+            $($arg: FromRequest,)*
+        {
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(self: Arc<Self>, mut request: HttpRequest) -> BoxFuture<'static, HttpResponse> {
+                async move {
+                    $(
+                        let $arg = match <$arg as FromRequest>::from_request(&mut request).await {
+                            Ok(value) => value,
+                            Err(rejection) => return rejection.into_response(),
+                        };
+                    )*
+                    (self)($($arg),*).await.into_response()
+                }
+                .boxed()
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(A1);
+impl_handler!(A1, A2);
+impl_handler!(A1, A2, A3);
+impl_handler!(A1, A2, A3, A4);
+impl_handler!(A1, A2, A3, A4, A5);
+impl_handler!(A1, A2, A3, A4, A5, A6);
+impl_handler!(A1, A2, A3, A4, A5, A6, A7);
+impl_handler!(A1, A2, A3, A4, A5, A6, A7, A8);
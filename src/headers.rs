@@ -0,0 +1,90 @@
+// Ordered list of header fields with case-insensitive lookups. Field names are
+// kept as they were given, so responses are written with the expected casing.
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers::default()
+    }
+
+    // Returns the first value of the header with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Returns all values of the header with the given name.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    // Replaces all values of the header with the given one.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    // Adds a value without touching the existing ones (e.g. for `Set-Cookie`).
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headers::Headers;
+
+    #[test]
+    fn test_lookup_is_case_insensitive() {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", "text/plain");
+
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+        assert!(headers.contains("CONTENT-TYPE"));
+    }
+
+    #[test]
+    fn test_insert_replaces_and_append_keeps_values() {
+        let mut headers = Headers::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        assert_eq!(
+            headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+
+        headers.insert("Set-Cookie", "c=3");
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), ["c=3"]);
+    }
+}
@@ -1,13 +1,41 @@
 pub mod alcazar;
+pub mod body;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod conditional;
+mod date;
 pub mod error;
+pub mod extensions;
+pub mod extract;
+pub mod fs;
+pub mod handler;
+pub mod headers;
+pub mod middleware;
+pub mod range;
 pub mod request;
+pub mod response;
 pub mod router;
 pub mod routing;
+#[cfg(feature = "tower")]
+pub mod service;
+#[cfg(feature = "session")]
+pub mod session;
+pub mod sse;
 pub mod status_code;
+pub mod upgrade;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 pub mod prelude {
     pub use crate::alcazar::{App, AppBuilder};
+    #[cfg(feature = "json")]
+    pub use crate::extract::Json;
+    pub use crate::extract::{Extension, Form, FromRequest, Path, Query, Rejection, State};
+    pub use crate::handler::Handler;
+    pub use crate::middleware::{Middleware, Next};
+    pub use crate::response::{HttpResponse, IntoResponse};
     pub use crate::router::Router;
     // TODO: Remove endpoint later from public APIs
     pub use crate::routing::endpoint::Endpoint;
+    pub use crate::routing::route::Route;
 }
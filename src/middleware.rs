@@ -0,0 +1,133 @@
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::routing::endpoint::BoxHandler;
+use futures::future::{BoxFuture, FutureExt};
+use std::future::Future;
+use std::sync::Arc;
+
+// Code running around the handlers. A middleware receives the request and the rest of
+// the pipeline as `Next`: it can modify the request before calling `next.run(request)`,
+// modify the response it gets back, or answer right away without calling it at all.
+//
+// Middleware run in the following order, each level in the order of registration:
+// - global middleware from `AppBuilder::with_middleware`, for every request
+// - middleware from `Router::with_middleware`, starting with the outermost router
+//   when routers are nested, for the requests routed to the router's endpoints
+// - middleware from `Route::middleware`, for the requests routed to the endpoint
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(&'a self, request: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse>;
+}
+
+impl<F, Fut> Middleware for F
+where
+    F: Fn(HttpRequest, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HttpResponse> + Send + 'static,
+{
+    fn handle<'a>(&'a self, request: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse> {
+        self(request, next).boxed()
+    }
+}
+
+// The remaining part of the pipeline: the following middleware, then the handler.
+#[derive(Clone)]
+pub struct Next {
+    middlewares: Arc<Vec<Arc<dyn Middleware>>>,
+    index: usize,
+    handler: BoxHandler,
+}
+
+impl Next {
+    pub(crate) fn new(middlewares: Vec<Arc<dyn Middleware>>, handler: BoxHandler) -> Self {
+        Next {
+            middlewares: Arc::new(middlewares),
+            index: 0,
+            handler,
+        }
+    }
+
+    pub fn run(self, request: HttpRequest) -> BoxFuture<'static, HttpResponse> {
+        match self.middlewares.get(self.index).cloned() {
+            Some(middleware) => {
+                let next = Next {
+                    index: self.index + 1,
+                    ..self
+                };
+                async move { middleware.handle(request, next).await }.boxed()
+            }
+            None => (self.handler)(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::middleware::Next;
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::routing::route::Route;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use futures::future::BoxFuture;
+    use lightproc::prelude::ProcStack;
+
+    async fn handler() -> &'static str {
+        "handler"
+    }
+
+    // Appends the label to the `X-Trace` header of the response
+    fn trace(
+        label: &'static str,
+    ) -> impl Fn(HttpRequest, Next) -> BoxFuture<'static, HttpResponse> {
+        move |request, next| {
+            Box::pin(async move {
+                let mut response = next.run(request).await;
+                let trace = match response.headers().get("X-Trace") {
+                    Some(trace) => format!("{},{}", trace, label),
+                    None => label.to_string(),
+                };
+                response.headers_mut().insert("X-Trace", &trace);
+                response
+            })
+        }
+    }
+
+    async fn deny(_request: HttpRequest, _next: Next) -> HttpResponse {
+        HttpResponse::new(StatusCode::Forbidden)
+    }
+
+    fn dispatch(router: &Router, path: &str) -> HttpResponse {
+        let request = HttpRequest::new(MethodType::GET, path);
+        run(router.dispatch(request), ProcStack::default())
+    }
+
+    #[test]
+    fn test_middleware_order() {
+        let admin = Router::new()
+            .with_middleware(trace("admin"))
+            .with_route(Route::new("/users", &["get"], handler).middleware(trace("endpoint")));
+        let router = Router::new()
+            .with_middleware(trace("root"))
+            .nest("/admin", admin);
+
+        let response = dispatch(&router, "/admin/users");
+        // Responses go through the middleware in the reversed order
+        assert_eq!(
+            response.headers().get("X-Trace"),
+            Some("endpoint,admin,root")
+        );
+        assert_eq!(response.body(), b"handler");
+    }
+
+    #[test]
+    fn test_middleware_short_circuits() {
+        let router = Router::new()
+            .with_middleware(deny)
+            .with_endpoint("/", &["get"], handler);
+
+        let response = dispatch(&router, "/");
+        assert_eq!(response.status(), StatusCode::Forbidden);
+        assert_eq!(response.body(), b"");
+    }
+}
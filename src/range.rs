@@ -0,0 +1,382 @@
+use crate::body::Body;
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, ResponseBody};
+use crate::routing::endpoint::MethodType;
+use crate::status_code::StatusCode;
+use futures::future::{BoxFuture, FutureExt};
+use std::io::{self, Cursor, Read};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Maximum number of ranges served in a response, requests asking for more of them
+// get the whole representation.
+const MAX_RANGES: usize = 16;
+
+// Part of the representation to send for a range request.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Ranges {
+    Full,
+    // Satisfiable ranges, in the order of the request
+    Partial(Vec<Range<u64>>),
+    Unsatisfiable,
+}
+
+// `Range` and `If-Range` headers of a request, kept to answer with the response.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RangeRequest {
+    range: Option<String>,
+    if_range: Option<String>,
+}
+
+impl RangeRequest {
+    pub(crate) fn from_request(request: &HttpRequest) -> Self {
+        // Ranges only apply to GET requests (RFC 9110, section 14.2)
+        if request.method() != &MethodType::GET {
+            return RangeRequest::default();
+        }
+        RangeRequest {
+            range: request.header("Range").map(str::to_string),
+            if_range: request.header("If-Range").map(str::to_string),
+        }
+    }
+
+    // Returns the ranges of the response's representation, of the given length, to send.
+    pub(crate) fn ranges(&self, response: &HttpResponse, length: u64) -> Ranges {
+        let range = match &self.range {
+            Some(range) if response.status() == StatusCode::Ok => range,
+            _ => return Ranges::Full,
+        };
+        // The representation may have changed since the client got its first part
+        let unchanged = self
+            .if_range
+            .as_deref()
+            .is_none_or(|validator| validator_matches(validator.trim(), response));
+        if !unchanged {
+            return Ranges::Full;
+        }
+        parse_ranges(range, length)
+    }
+}
+
+// Compares the validator of `If-Range` to the response's, which must be a strong
+// entity tag or the modification date.
+fn validator_matches(validator: &str, response: &HttpResponse) -> bool {
+    let header = |name| response.headers().get(name).map(str::trim);
+    if validator.starts_with("W/") {
+        false
+    } else if validator.starts_with('"') {
+        header("ETag") == Some(validator)
+    } else {
+        header("Last-Modified") == Some(validator)
+    }
+}
+
+fn parse_position(position: &str) -> Option<u64> {
+    let position = position.trim();
+    if position.is_empty() || !position.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    position.parse().ok()
+}
+
+// Parses the value of a `Range` header for a representation of the given length.
+// Invalid headers are ignored, as required by RFC 9110.
+pub(crate) fn parse_ranges(header: &str, length: u64) -> Ranges {
+    let header = header.trim();
+    let specs = match header.split_once('=') {
+        Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => specs,
+        _ => return Ranges::Full,
+    };
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        count += 1;
+        let (first, last) = match spec.split_once('-') {
+            Some(positions) => positions,
+            None => return Ranges::Full,
+        };
+        let range = if first.trim().is_empty() {
+            // `-500` asks for the last 500 bytes
+            match parse_position(last) {
+                Some(suffix) => length.saturating_sub(suffix)..length,
+                None => return Ranges::Full,
+            }
+        } else {
+            let first = match parse_position(first) {
+                Some(first) => first,
+                None => return Ranges::Full,
+            };
+            let end = match last.trim() {
+                "" => length,
+                last => match parse_position(last) {
+                    Some(last) if last >= first => last.saturating_add(1).min(length),
+                    _ => return Ranges::Full,
+                },
+            };
+            first..end
+        };
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+
+    match count {
+        0 => Ranges::Full,
+        _ if ranges.is_empty() => Ranges::Unsatisfiable,
+        _ if ranges.len() > MAX_RANGES => Ranges::Full,
+        _ => Ranges::Partial(ranges),
+    }
+}
+
+fn content_range(range: &Range<u64>, length: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, length)
+}
+
+// Returns a delimiter of the `multipart/byteranges` parts unlikely to be in the data.
+fn boundary() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("alcazar-{:x}-{:x}", nanos, count)
+}
+
+// Turns the full response into a 206 Partial Content one, with the parts given by
+// `read_part`. A single range is sent as is, while several ones are sent as a
+// `multipart/byteranges` body. The body is streamed when `stream` is set.
+pub(crate) fn partial_response<F>(
+    mut response: HttpResponse,
+    ranges: &[Range<u64>],
+    length: u64,
+    stream: bool,
+    read_part: F,
+) -> io::Result<HttpResponse>
+where
+    F: Fn(&Range<u64>) -> io::Result<Box<dyn Read + Send>>,
+{
+    response.set_status(StatusCode::PartialContent);
+    let (reader, content_length) = match ranges {
+        [range] => {
+            let content_range = content_range(range, length);
+            response
+                .headers_mut()
+                .insert("Content-Range", &content_range);
+            (read_part(range)?, range.end - range.start)
+        }
+        _ => {
+            let boundary = boundary();
+            let content_type = response.headers().get("Content-Type").map(str::to_string);
+            let mut reader: Box<dyn Read + Send> = Box::new(io::empty());
+            let mut content_length = 0;
+            for (index, range) in ranges.iter().enumerate() {
+                let mut head = match index {
+                    0 => format!("--{}\r\n", boundary),
+                    _ => format!("\r\n--{}\r\n", boundary),
+                };
+                if let Some(content_type) = &content_type {
+                    head.push_str(&format!("Content-Type: {}\r\n", content_type));
+                }
+                head.push_str(&format!(
+                    "Content-Range: {}\r\n\r\n",
+                    content_range(range, length)
+                ));
+                content_length += head.len() as u64 + range.end - range.start;
+                let part = Cursor::new(head.into_bytes()).chain(read_part(range)?);
+                reader = Box::new(reader.chain(part));
+            }
+            let tail = format!("\r\n--{}--\r\n", boundary);
+            content_length += tail.len() as u64;
+            reader = Box::new(reader.chain(Cursor::new(tail.into_bytes())));
+
+            let content_type = format!("multipart/byteranges; boundary={}", boundary);
+            response.headers_mut().insert("Content-Type", &content_type);
+            (reader, content_length)
+        }
+    };
+
+    response
+        .headers_mut()
+        .insert("Content-Length", &content_length.to_string());
+    if stream {
+        Ok(response.with_stream(Body::from_reader(reader)))
+    } else {
+        let mut reader = reader;
+        let mut body = Vec::with_capacity(content_length as usize);
+        reader.read_to_end(&mut body)?;
+        Ok(response.with_body(body))
+    }
+}
+
+// Turns the full response into a 416 Range Not Satisfiable one.
+pub(crate) fn unsatisfiable_response(mut response: HttpResponse, length: u64) -> HttpResponse {
+    response.set_status(StatusCode::RequestedRangeNotSatisfiable);
+    response.set_body(ResponseBody::empty());
+    response.headers_mut().remove("Content-Length");
+    response
+        .headers_mut()
+        .insert("Content-Range", &format!("bytes */{}", length));
+    response
+}
+
+// Middleware answering the range requests with the parts of the complete bodies,
+// and announcing the support with `Accept-Ranges`. Streamed bodies are sent whole,
+// except the files of `ServeFile` and `ServeDir` which handle the ranges themselves.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RangeMiddleware;
+
+impl Middleware for RangeMiddleware {
+    fn handle<'a>(&'a self, request: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse> {
+        let range = RangeRequest::from_request(&request);
+        async move {
+            let mut response = next.run(request).await;
+            if response.status() != StatusCode::Ok || response.is_stream() {
+                return response;
+            }
+            response.headers_mut().insert("Accept-Ranges", "bytes");
+
+            let length = response.body().len() as u64;
+            match range.ranges(&response, length) {
+                Ranges::Full => response,
+                Ranges::Unsatisfiable => unsatisfiable_response(response, length),
+                Ranges::Partial(ranges) => {
+                    let body = match response.take_body() {
+                        ResponseBody::Bytes(body) => body,
+                        ResponseBody::Stream(_) => Vec::new(),
+                    };
+                    let read_part = |range: &Range<u64>| {
+                        let part = body[range.start as usize..range.end as usize].to_vec();
+                        Ok(Box::new(Cursor::new(part)) as Box<dyn Read + Send>)
+                    };
+                    partial_response(response, &ranges, length, false, read_part)
+                        .unwrap_or_else(|_| HttpResponse::new(StatusCode::InternalServerError))
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::range::{parse_ranges, RangeMiddleware, Ranges};
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use std::ops::Range;
+
+    async fn alphabet() -> HttpResponse {
+        HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain")
+            .with_header("ETag", "\"v1\"")
+            .with_body("abcdefghijklmnopqrstuvwxyz")
+    }
+
+    fn get(headers: &[(&str, &str)]) -> HttpResponse {
+        let router = Router::new()
+            .with_middleware(RangeMiddleware)
+            .with_endpoint("/", &["get"], alphabet);
+        let mut request = HttpRequest::new(MethodType::GET, "/");
+        for (name, value) in headers {
+            request = request.with_header(name, value);
+        }
+        run(router.dispatch(request), ProcStack::default())
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=0-4", 10),
+            Ranges::Partial(vec![Range { start: 0, end: 5 }])
+        );
+        assert_eq!(
+            parse_ranges("bytes=2-, -3, 8-20", 10),
+            Ranges::Partial(vec![2..10, 7..10, 8..10])
+        );
+        assert_eq!(parse_ranges("bytes=10-, -0", 10), Ranges::Unsatisfiable);
+        // Invalid headers are ignored
+        for header in [
+            "items=0-4",
+            "bytes=4-2",
+            "bytes=a-b",
+            "bytes=",
+            "bytes=+1-2",
+        ] {
+            assert_eq!(parse_ranges(header, 10), Ranges::Full, "{}", header);
+        }
+        let many = format!("bytes={}", vec!["0-0"; 17].join(","));
+        assert_eq!(parse_ranges(&many, 10), Ranges::Full);
+    }
+
+    #[test]
+    fn test_single_range() {
+        let response = get(&[("Range", "bytes=2-4")]);
+        assert_eq!(response.status(), StatusCode::PartialContent);
+        assert_eq!(
+            response.headers().get("Content-Range"),
+            Some("bytes 2-4/26")
+        );
+        assert_eq!(response.headers().get("Content-Length"), Some("3"));
+        assert_eq!(response.headers().get("Accept-Ranges"), Some("bytes"));
+        assert_eq!(response.body(), b"cde");
+
+        let response = get(&[]);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.headers().get("Accept-Ranges"), Some("bytes"));
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        let response = get(&[("Range", "bytes=0-1, -2")]);
+        assert_eq!(response.status(), StatusCode::PartialContent);
+        let content_type = response.headers().get("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let expected = format!(
+            "--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/26\r\n\r\nab\
+             \r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 24-25/26\r\n\r\nyz\
+             \r\n--{0}--\r\n",
+            boundary
+        );
+        assert_eq!(String::from_utf8_lossy(response.body()), expected);
+        let length = expected.len().to_string();
+        assert_eq!(
+            response.headers().get("Content-Length"),
+            Some(length.as_str())
+        );
+    }
+
+    #[test]
+    fn test_unsatisfiable_range() {
+        let response = get(&[("Range", "bytes=30-")]);
+        assert_eq!(response.status(), StatusCode::RequestedRangeNotSatisfiable);
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes */26"));
+        assert_eq!(response.body(), b"");
+    }
+
+    #[test]
+    fn test_if_range() {
+        let response = get(&[("Range", "bytes=0-0"), ("If-Range", "\"v1\"")]);
+        assert_eq!(response.status(), StatusCode::PartialContent);
+        assert_eq!(response.body(), b"a");
+
+        // The whole representation is sent when it has changed
+        for validator in ["\"v0\"", "W/\"v1\"", "Wed, 21 Oct 2015 07:28:00 GMT"] {
+            let response = get(&[("Range", "bytes=0-0"), ("If-Range", validator)]);
+            assert_eq!(response.status(), StatusCode::Ok, "{}", validator);
+            assert_eq!(response.body().len(), 26);
+        }
+    }
+}
@@ -1,21 +1,45 @@
-use crate::error::{AlcazarError, HttpError, ParseError, Result};
+use crate::body::Body;
+use crate::error::{AlcazarError, ParseError, Result};
+use crate::extensions::{AppState, Extensions};
+use crate::headers::Headers;
+#[cfg(feature = "tower")]
+use crate::middleware::Next;
 use crate::routing::endpoint::MethodType;
+use crate::upgrade::{self, Connection};
 use httparse::{Error as HttpParseError, Request, EMPTY_HEADER};
 use std::io::{BufRead, BufReader};
 use std::net::TcpStream;
 use std::str::FromStr;
+use std::sync::Arc;
 use tracing::info;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
 pub struct HttpRequest {
+    version: Version,
     path: String,
+    query: Option<String>,
     method: MethodType,
+    headers: Headers,
+    // Values of the dynamic parts of the matched route
+    params: Vec<(String, String)>,
+    state: Arc<AppState>,
+    extensions: Extensions,
+    body: Body,
+    // Rest of the pipeline wrapped by a tower layer, kept by the clones of the request
+    #[cfg(feature = "tower")]
+    next: Option<Next>,
 }
 
 // See https://users.rust-lang.org/t/curl-post-tcpstream/38350/3 for understand how to handle a TcpStream as HttpRequest
 impl HttpRequest {
     pub(crate) fn parse_stream(stream: &TcpStream) -> Result<HttpRequest> {
         // Creating the reader and the buffer for read the stream
-        let mut reader = BufReader::new(stream);
+        let mut reader = BufReader::new(stream.try_clone()?);
         let mut buffer = String::new();
         // Read the stream line by line and add it to the buffer
         loop {
@@ -30,11 +54,14 @@ impl HttpRequest {
                         break;
                     }
                 }
-                Err(error) => info!("An error occured during request parsing: {}", error),
+                Err(error) => {
+                    info!("An error occured during request parsing: {}", error);
+                    return Err(error.into());
+                }
             }
         }
         // Create headers for parse the request with the crate Httparse
-        let mut headers = [EMPTY_HEADER; 16];
+        let mut headers = [EMPTY_HEADER; 64];
         let mut request = Request::new(&mut headers[..]);
         // Getting the status of the request
         let request_status = match request.parse(buffer.as_ref()) {
@@ -46,15 +73,51 @@ impl HttpRequest {
         // If the request is complete we are returning the response in the stream
         if request_status.is_complete() {
             info!("Request is complete.");
-            HttpRequest::parse_request(request)
+            let mut request = HttpRequest::parse_request(request)?;
+            // The body is read from the reader, as it may have buffered its beginning,
+            // while the upgrade requests keep the whole connection for the new protocol
+            if upgrade::wants_upgrade(&request) {
+                request.extensions.insert(Connection::new(reader));
+            } else {
+                request.body = Body::from_connection(reader, &request.headers)?;
+            }
+            Ok(request)
         } else {
-            Err(AlcazarError::HttpError(HttpError::PartialContent))
+            Err(AlcazarError::ParseError(ParseError::IncompleteRequest))
+        }
+    }
+
+    // Returns a request with the given method and path, mainly useful for testing.
+    pub fn new(method: MethodType, target: &str) -> Self {
+        let (path, query) = split_target(target);
+        HttpRequest {
+            version: Version::Http11,
+            path,
+            query,
+            method,
+            headers: Headers::new(),
+            params: Vec::new(),
+            state: Arc::default(),
+            extensions: Extensions::new(),
+            body: Body::empty(),
+            #[cfg(feature = "tower")]
+            next: None,
         }
     }
 
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn with_body<B: Into<Body>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
     fn parse_request(request: Request) -> Result<HttpRequest> {
-        let path = match request.path.map(String::from) {
-            Some(path) => Ok(path),
+        let (path, query) = match request.path.map(split_target) {
+            Some(target) => Ok(target),
             None => Err(AlcazarError::ParseError(ParseError::PathMissing)),
         }?;
         let method = match request.method {
@@ -62,16 +125,168 @@ impl HttpRequest {
             None => Err(AlcazarError::ParseError(ParseError::MethodMissing)),
         }?;
         let method = MethodType::from_str(method)?;
+        let mut headers = Headers::new();
+        for header in request.headers.iter() {
+            headers.append(header.name, &String::from_utf8_lossy(header.value));
+        }
 
-        Ok(HttpRequest { path, method })
+        let version = match request.version {
+            Some(0) => Version::Http10,
+            _ => Version::Http11,
+        };
+
+        Ok(HttpRequest {
+            version,
+            path,
+            query,
+            method,
+            headers,
+            params: Vec::new(),
+            state: Arc::default(),
+            extensions: Extensions::new(),
+            body: Body::empty(),
+            #[cfg(feature = "tower")]
+            next: None,
+        })
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
     }
 
     pub fn path(&self) -> &str {
         self.path.as_ref()
     }
 
-    pub fn method(&self) -> MethodType {
-        self.method
+    // Returns the raw query string, without the leading question mark.
+    pub fn query(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+
+    pub fn method(&self) -> &MethodType {
+        &self.method
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    // Returns the first value of the header with the given name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    // Returns the lowercased media type of the body, without its parameters
+    // (e.g. `application/json` for `application/json; charset=utf-8`).
+    pub fn media_type(&self) -> Option<String> {
+        self.header("Content-Type").map(|content_type| {
+            let media_type = content_type.split(';').next().unwrap_or("");
+            media_type.trim().to_ascii_lowercase()
+        })
+    }
+
+    // Returns the value of the route parameter (including the ones captured by guards).
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param_name, _)| param_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    pub(crate) fn set_params(&mut self, params: Vec<(String, String)>) {
+        self.params = params;
+    }
+
+    // Returns the application state of the given type, see `AppBuilder::with_state`.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get()
+    }
+
+    pub(crate) fn app_state(&self) -> &AppState {
+        &self.state
+    }
+
+    pub(crate) fn set_state(&mut self, state: Arc<AppState>) {
+        self.state = state;
+    }
+
+    // Values attached to the request, mainly by middleware for the handlers.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    pub fn body_mut(&mut self) -> &mut Body {
+        &mut self.body
+    }
+
+    // Takes the body out of the request, leaving an empty one.
+    pub fn take_body(&mut self) -> Body {
+        std::mem::take(&mut self.body)
+    }
+
+    // Moves the request out, leaving a copy of its head behind for the extractors
+    // that may run afterwards. The body and the extensions go with the returned request.
+    pub(crate) fn take(&mut self) -> HttpRequest {
+        HttpRequest {
+            version: self.version,
+            path: self.path.clone(),
+            query: self.query.clone(),
+            method: self.method.clone(),
+            headers: self.headers.clone(),
+            params: self.params.clone(),
+            state: self.state.clone(),
+            extensions: std::mem::take(&mut self.extensions),
+            body: self.take_body(),
+            #[cfg(feature = "tower")]
+            next: self.next.clone(),
+        }
+    }
+
+    // Clones the request, when its body is held in memory (see `Body::buffer`), e.g.
+    // for the retry policies. The extensions aren't cloned.
+    pub fn try_clone(&self) -> Option<HttpRequest> {
+        Some(HttpRequest {
+            version: self.version,
+            path: self.path.clone(),
+            query: self.query.clone(),
+            method: self.method.clone(),
+            headers: self.headers.clone(),
+            params: self.params.clone(),
+            state: self.state.clone(),
+            extensions: Extensions::new(),
+            body: self.body.try_clone()?,
+            #[cfg(feature = "tower")]
+            next: self.next.clone(),
+        })
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn set_next(&mut self, next: Next) {
+        self.next = Some(next);
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn take_next(&mut self) -> Option<Next> {
+        self.next.take()
+    }
+}
+
+// Splits the request target into the path and the query string.
+fn split_target(target: &str) -> (String, Option<String>) {
+    match target.find('?') {
+        Some(index) => (
+            target[..index].to_string(),
+            Some(target[index + 1..].to_string()),
+        ),
+        None => (target.to_string(), None),
     }
 }
 
@@ -79,8 +294,9 @@ impl HttpRequest {
 mod tests {
     use crate::router::Router;
     use crate::{alcazar::AppBuilder, status_code::StatusCode};
-    use std::io::{BufRead, BufReader, Write};
+    use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
     use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+    use std::time::Duration;
 
     fn get_ipv4_socket_addr() -> SocketAddr {
         SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0)
@@ -112,4 +328,70 @@ mod tests {
 
         assert_eq!(buffer, "HTTP/1.1 200 OK\r\n");
     }
+
+    async fn echo(body: String) -> String {
+        body
+    }
+
+    #[test]
+    fn parse_stream_with_body() {
+        let router = Router::new().with_endpoint("/", &["post"], echo);
+        let alcazar = AppBuilder::default()
+            .set_addr(get_ipv4_socket_addr())
+            .set_router(router)
+            .start()
+            .expect("unwrap appbuilder");
+
+        let mut stream = TcpStream::connect(alcazar.local_addr()).expect("unwrap connect");
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 13\r\n\r\nHello, world!")
+            .expect("unwrap write_all");
+        stream.flush().expect("unwrap flush");
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .expect("unwrap read_to_string");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nHello, world!"));
+    }
+
+    fn send_request(request: &[u8]) -> io::Result<Vec<u8>> {
+        let router = Router::new().with_endpoint("/", &["post"], echo);
+        let alcazar = AppBuilder::default()
+            .set_addr(get_ipv4_socket_addr())
+            .set_router(router)
+            .start()
+            .expect("unwrap appbuilder");
+
+        let mut stream = TcpStream::connect(alcazar.local_addr()).expect("unwrap connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("unwrap set_read_timeout");
+        stream.write_all(request).expect("unwrap write_all");
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map(|_| response)
+    }
+
+    #[test]
+    fn parse_stream_with_unsupported_transfer_coding() {
+        let response = send_request(b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n")
+            .expect("unwrap read_to_end");
+        let response = String::from_utf8(response).expect("unwrap from_utf8");
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("\"gzip\" is not a supported transfer coding"));
+    }
+
+    #[test]
+    fn parse_stream_with_read_error() {
+        // The head isn't valid UTF-8, the connection is closed instead of waiting
+        let result = send_request(b"GET /\xff HTTP/1.1\r\n\r\n");
+        match result {
+            Ok(response) => assert!(response.is_empty()),
+            Err(err) => assert_eq!(err.kind(), ErrorKind::ConnectionReset),
+        }
+    }
 }
@@ -0,0 +1,393 @@
+use crate::conditional::ETag;
+use crate::date;
+use crate::headers::Headers;
+use crate::request::Version;
+use crate::status_code::StatusCode;
+use crate::upgrade::OnUpgrade;
+use cookie::Cookie;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+// Body of the response, either complete or streamed in chunks. Streamed bodies are
+// written with the chunked transfer coding on HTTP/1.1, unless the response has a
+// `Content-Length`, and delimited by closing the connection on HTTP/1.0.
+pub enum ResponseBody {
+    Bytes(Vec<u8>),
+    Stream(BoxStream<'static, io::Result<Vec<u8>>>),
+}
+
+impl ResponseBody {
+    pub fn empty() -> Self {
+        ResponseBody::Bytes(Vec::new())
+    }
+}
+
+impl Default for ResponseBody {
+    fn default() -> Self {
+        ResponseBody::empty()
+    }
+}
+
+impl fmt::Debug for ResponseBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseBody::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            ResponseBody::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HttpResponse {
+    status: StatusCode,
+    headers: Headers,
+    body: ResponseBody,
+    // Boxed, as it would make every response larger
+    upgrade: Option<Box<OnUpgrade>>,
+}
+
+impl HttpResponse {
+    pub fn new(status: StatusCode) -> Self {
+        HttpResponse {
+            status,
+            headers: Headers::new(),
+            body: ResponseBody::empty(),
+            upgrade: None,
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_cookie(mut self, cookie: Cookie<'_>) -> Self {
+        self.add_cookie(cookie);
+        self
+    }
+
+    // Sets the `ETag` header, used to answer the conditional requests.
+    pub fn with_etag(self, etag: &ETag) -> Self {
+        self.with_header("ETag", &etag.to_string())
+    }
+
+    // Sets the `Last-Modified` header, used to answer the conditional requests.
+    pub fn with_last_modified(self, time: SystemTime) -> Self {
+        self.with_header("Last-Modified", &date::format_http_date(time))
+    }
+
+    pub fn with_body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = ResponseBody::Bytes(body.into());
+        self
+    }
+
+    // Streams the body from the chunks, which are sent as soon as they are ready.
+    // The next chunk isn't polled until the previous one is written to the client.
+    pub fn with_stream<S, B, E>(mut self, stream: S) -> Self
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: Into<Vec<u8>>,
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let stream = stream.map(|chunk| chunk.map(Into::into).map_err(io::Error::other));
+        self.body = ResponseBody::Stream(stream.boxed());
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.status = status;
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    // Sets the cookie on the client, with a `Set-Cookie` header.
+    pub fn add_cookie(&mut self, cookie: Cookie<'_>) {
+        self.headers
+            .append("Set-Cookie", &cookie.encoded().to_string());
+    }
+
+    // Removes the cookie from the client by expiring it. Its path and domain must
+    // match the ones it was set with.
+    pub fn remove_cookie(&mut self, cookie: Cookie<'_>) {
+        let mut cookie = cookie.into_owned();
+        cookie.make_removal();
+        self.add_cookie(cookie);
+    }
+
+    // Returns the complete body, which is empty for streamed bodies.
+    pub fn body(&self) -> &[u8] {
+        match &self.body {
+            ResponseBody::Bytes(bytes) => bytes,
+            ResponseBody::Stream(_) => &[],
+        }
+    }
+
+    pub fn is_stream(&self) -> bool {
+        matches!(self.body, ResponseBody::Stream(_))
+    }
+
+    // Takes the body out of the response, leaving an empty one.
+    pub fn take_body(&mut self) -> ResponseBody {
+        std::mem::take(&mut self.body)
+    }
+
+    pub fn set_body(&mut self, body: ResponseBody) {
+        self.body = body;
+    }
+
+    // Takes over the connection after a `101 Switching Protocols` response.
+    pub(crate) fn with_upgrade(mut self, upgrade: OnUpgrade) -> Self {
+        self.upgrade = Some(Box::new(upgrade));
+        self
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<OnUpgrade> {
+        self.upgrade.take().map(|upgrade| *upgrade)
+    }
+
+    // Drops the body but keeps announcing its length, as required for HEAD responses.
+    pub(crate) fn strip_body(&mut self) {
+        if !self.headers.contains("Content-Length") {
+            match &self.body {
+                ResponseBody::Bytes(bytes) => {
+                    let length = bytes.len().to_string();
+                    self.headers.insert("Content-Length", &length);
+                }
+                ResponseBody::Stream(_) => self.headers.insert("Transfer-Encoding", "chunked"),
+            }
+        }
+        self.body = ResponseBody::empty();
+    }
+
+    // Serializes the status line and the headers, adding the `Content-Length` of
+    // complete bodies when the framing isn't given.
+    fn head_bytes(&self, body_length: Option<usize>) -> Vec<u8> {
+        let mut bytes = self.status.into_bytes_response();
+        for (name, value) in self.headers.iter() {
+            bytes.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        let framed =
+            self.headers.contains("Content-Length") || self.headers.contains("Transfer-Encoding");
+        if let Some(length) = body_length.filter(|_| !framed && self.status.allows_body()) {
+            bytes.extend_from_slice(format!("Content-Length: {}\r\n", length).as_bytes());
+        }
+        bytes.extend_from_slice(b"\r\n");
+        bytes
+    }
+
+    // Serializes the status line, the headers and the body. Streamed bodies are left
+    // out, as they are only written to the connection by `write_to`.
+    pub fn into_bytes_response(self) -> Vec<u8> {
+        match self.body {
+            ResponseBody::Bytes(ref body) => {
+                let mut bytes = self.head_bytes(Some(body.len()));
+                bytes.extend_from_slice(body);
+                bytes
+            }
+            ResponseBody::Stream(_) => self.head_bytes(None),
+        }
+    }
+
+    // Writes the status line and the headers only, for the responses taking over the
+    // connection, which can't have a body.
+    pub(crate) fn write_head_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.head_bytes(None))?;
+        writer.flush()
+    }
+
+    // Writes the response for a request of the given version, flushing every chunk
+    // of a streamed body.
+    pub(crate) async fn write_to<W: Write>(
+        mut self,
+        writer: &mut W,
+        version: Version,
+    ) -> io::Result<()> {
+        let mut stream = match self.take_body() {
+            ResponseBody::Bytes(body) => {
+                self.body = ResponseBody::Bytes(body);
+                writer.write_all(&self.into_bytes_response())?;
+                return writer.flush();
+            }
+            ResponseBody::Stream(stream) => stream,
+        };
+
+        let chunked = !self.headers.contains("Content-Length")
+            && self.status.allows_body()
+            && version == Version::Http11;
+        if chunked {
+            self.headers.insert("Transfer-Encoding", "chunked");
+        } else if version == Version::Http10 {
+            self.headers.insert("Connection", "close");
+        }
+        writer.write_all(&self.head_bytes(None))?;
+        writer.flush()?;
+        if !self.status.allows_body() {
+            return Ok(());
+        }
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            if chunk.is_empty() {
+                continue;
+            }
+            if chunked {
+                writer.write_all(format!("{:x}\r\n", chunk.len()).as_bytes())?;
+                writer.write_all(&chunk)?;
+                writer.write_all(b"\r\n")?;
+            } else {
+                writer.write_all(&chunk)?;
+            }
+            writer.flush()?;
+        }
+        if chunked {
+            writer.write_all(b"0\r\n\r\n")?;
+        }
+        writer.flush()
+    }
+}
+
+// Conversion of the handler's output into a response.
+pub trait IntoResponse {
+    fn into_response(self) -> HttpResponse;
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self) -> HttpResponse {
+        self
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::new(self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(self)
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> HttpResponse {
+        self.to_string().into_response()
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "application/octet-stream")
+            .with_body(self)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> HttpResponse {
+        let (status, body) = self;
+        let mut response = body.into_response();
+        response.set_status(status);
+        response
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> HttpResponse {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::Version;
+    use crate::response::{HttpResponse, IntoResponse};
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use cookie::time::Duration;
+    use cookie::{Cookie, SameSite};
+    use futures::stream;
+    use lightproc::prelude::ProcStack;
+    use std::io;
+
+    #[test]
+    fn test_serialize_response_with_body() {
+        let response = (StatusCode::Created, "hello").into_response();
+
+        let bytes = response.into_bytes_response();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "HTTP/1.1 201 Created\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: 5\r\n\r\nhello"
+        );
+    }
+
+    #[test]
+    fn test_set_and_remove_cookies() {
+        let cookie = Cookie::build(("id", "a b"))
+            .domain("example.com")
+            .path("/app")
+            .max_age(Duration::hours(1))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        let mut response = HttpResponse::new(StatusCode::Ok).with_cookie(cookie.into());
+        response.remove_cookie(Cookie::build("theme").path("/").into());
+
+        let cookies: Vec<&str> = response.headers().get_all("Set-Cookie").collect();
+        assert_eq!(
+            cookies[0],
+            "id=a%20b; HttpOnly; SameSite=Lax; Secure; Path=/app; Domain=example.com; Max-Age=3600"
+        );
+        assert!(cookies[1].starts_with("theme=; Path=/; Max-Age=0; Expires="));
+    }
+
+    #[test]
+    fn test_write_stream_with_content_length() {
+        let chunks = vec![Ok::<_, io::Error>("hello, "), Ok("world")];
+        let response = HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Length", "12")
+            .with_stream(stream::iter(chunks));
+
+        let mut bytes = Vec::new();
+        run(
+            response.write_to(&mut bytes, Version::Http11),
+            ProcStack::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nhello, world"
+        );
+    }
+
+    #[test]
+    fn test_strip_body_keeps_content_length() {
+        let mut response = HttpResponse::new(StatusCode::Ok).with_body("hello");
+        response.strip_body();
+
+        let bytes = response.into_bytes_response();
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n"
+        );
+    }
+}
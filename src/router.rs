@@ -1,19 +1,36 @@
-use crate::error::{AlcazarError, HttpError, Result};
+use crate::error::{AlcazarError, HttpError, Result, RoutingError};
+#[cfg(feature = "websocket")]
+use crate::websocket::{WebSocket, WebSocketUpgrade};
 use crate::{
+    handler::Handler,
+    middleware::Middleware,
+    request::HttpRequest,
+    response::HttpResponse,
     routing::endpoint::{Endpoint, MethodType},
+    routing::pattern::{overlap, MatchedRoute, Overlap},
+    routing::route::Route,
     status_code::StatusCode,
 };
-use std::{future::Future, str::FromStr};
+#[cfg(feature = "websocket")]
+use std::future::Future;
+use std::{result, sync::Arc};
+use tracing::warn;
 
 #[derive(Clone)]
 pub struct Router {
     endpoints: Vec<Endpoint>,
+    // Registration failures, reported all together by `build`
+    errors: Vec<RoutingError>,
+    middlewares: Vec<Arc<dyn Middleware>>,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Router {
     fn default() -> Self {
         Self {
             endpoints: Vec::new(),
+            errors: Vec::new(),
+            middlewares: Vec::new(),
         }
     }
 }
@@ -28,44 +45,519 @@ impl Router {
         &self.endpoints
     }
 
-    pub fn with_endpoint<C, F>(mut self, path: &str, methods: &[&str], exec: C) -> Self
+    pub fn with_endpoint<H, Args>(self, path: &str, methods: &[&str], exec: H) -> Self
     where
-        C: Fn() -> F + Send + Sync + 'static,
-        F: Future<Output = StatusCode> + Send + Sync + 'static,
+        H: Handler<Args>,
     {
-        let acceptable_methods = methods
-            .iter()
-            .map(|method| {
-                let fixed_method_name = method.trim().to_uppercase();
-                MethodType::from_str(&fixed_method_name)
-            })
-            .filter_map(|method| method.ok())
-            .collect();
-
-        match Endpoint::new(path, acceptable_methods, exec) {
+        self.with_route(Route::new(path, methods, exec))
+    }
+
+    // Same as `with_endpoint`, but returns the error instead of skipping the endpoint.
+    pub fn try_with_endpoint<H, Args>(
+        self,
+        path: &str,
+        methods: &[&str],
+        exec: H,
+    ) -> result::Result<Self, RoutingError>
+    where
+        H: Handler<Args>,
+    {
+        self.try_with_route(Route::new(path, methods, exec))
+    }
+
+    // Adds a GET endpoint accepting WebSocket connections, which are handed to the
+    // callback once the handshake is done. Handlers taking a `WebSocketUpgrade`
+    // can be used instead to inspect the request first.
+    #[cfg(feature = "websocket")]
+    pub fn with_websocket<F, Fut>(self, path: &str, callback: F) -> Self
+    where
+        F: Fn(WebSocket) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let callback = Arc::new(callback);
+        self.with_endpoint(path, &["get"], move |upgrade: WebSocketUpgrade| {
+            let callback = callback.clone();
+            async move { upgrade.on_upgrade(move |socket| callback(socket)) }
+        })
+    }
+
+    // Adds an endpoint declared with its settings (e.g. a name).
+    pub fn with_route(mut self, route: Route) -> Self {
+        match route.into_endpoint() {
             Ok(endpoint) => {
                 self.endpoints.push(endpoint);
             }
-            Err(err) => println!("{:?}, The endpoint has been skipped.", err),
+            Err(err) => {
+                warn!("{} The endpoint has been skipped.", err);
+                self.errors.push(err);
+            }
         };
 
         self
     }
 
-    // Merges two routers together.
+    // Same as `with_route`, but returns the error instead of skipping the endpoint.
+    pub fn try_with_route(mut self, route: Route) -> result::Result<Self, RoutingError> {
+        let endpoint = route.into_endpoint()?;
+        self.endpoints.push(endpoint);
+        Ok(self)
+    }
+
+    // Adds a middleware running for the requests routed to any endpoint of the router.
+    pub fn with_middleware<M: Middleware>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    // Merges two routers together. The middleware of the included router keep
+    // applying only to its own endpoints.
     pub fn include(mut self, router: &Router) -> Self {
-        self.endpoints.extend(router.endpoints().iter().cloned());
+        for endpoint in router.endpoints() {
+            let mut endpoint = endpoint.clone();
+            endpoint.wrap(&router.middlewares);
+            self.endpoints.push(endpoint);
+        }
+        self.errors.extend(router.errors.iter().cloned());
+        self
+    }
+
+    // Mounts all endpoints of the router under the given prefix, which may also
+    // contain dynamic parts (e.g. `/api/{version}`).
+    pub fn nest(mut self, prefix: &str, router: Router) -> Self {
+        for endpoint in router.endpoints() {
+            match endpoint.with_prefix(prefix) {
+                Ok(mut endpoint) => {
+                    endpoint.wrap(&router.middlewares);
+                    self.endpoints.push(endpoint);
+                }
+                Err(err) => {
+                    warn!("{} The endpoint has been skipped.", err);
+                    self.errors.push(err);
+                }
+            }
+        }
+        self.errors.extend(router.errors);
         self
     }
 
-    // Returns an endpoint by the given path and the method.
-    pub fn get_endpoint(&self, method: MethodType, path: &str) -> Result<&Endpoint> {
-        for endpoint in &self.endpoints {
-            let pattern = endpoint.pattern();
-            if pattern.is_match(path) && endpoint.methods().contains(&method) {
-                return Ok(endpoint);
+    // Declares a group of endpoints sharing the same prefix.
+    pub fn scope<S>(self, prefix: &str, scope: S) -> Self
+    where
+        S: FnOnce(Router) -> Router,
+    {
+        self.nest(prefix, scope(Router::new()))
+    }
+
+    // Validates the declared routes, reporting invalid patterns, duplicated routes
+    // and routes that can be shadowed by the ones declared earlier, all at once.
+    pub fn build(self) -> result::Result<Self, RoutingError> {
+        let mut errors = self.errors.clone();
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            for earlier in &self.endpoints[..index] {
+                if let (Some(name), Some(earlier_name)) = (endpoint.name(), earlier.name()) {
+                    if name == earlier_name {
+                        errors.push(RoutingError::DuplicateNameError(name.to_string()));
+                    }
+                }
+
+                // Guarded endpoints let the requests they reject fall through to later ones
+                if earlier.has_guards() {
+                    continue;
+                }
+
+                let shared_methods: Vec<String> = endpoint
+                    .methods()
+                    .iter()
+                    .filter(|method| earlier.methods().contains(method))
+                    .map(MethodType::to_string)
+                    .collect();
+                if shared_methods.is_empty() {
+                    continue;
+                }
+
+                let methods = shared_methods.join(", ");
+                let path = endpoint.path().to_string();
+                match overlap(earlier.path(), endpoint.path()) {
+                    Overlap::Disjoint | Overlap::Narrower => {}
+                    Overlap::Same if earlier.path() == endpoint.path() => {
+                        errors.push(RoutingError::DuplicateRouteError { methods, path });
+                    }
+                    Overlap::Same | Overlap::Wider | Overlap::Ambiguous => {
+                        errors.push(RoutingError::AmbiguousRouteError {
+                            methods,
+                            path,
+                            other: earlier.path().to_string(),
+                        });
+                    }
+                }
             }
         }
-        Err(AlcazarError::HttpError(HttpError::InternalServerError))
+
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(RoutingError::InvalidRoutesError(errors))
+        }
+    }
+
+    // Builds the URL path of the named route from the values of its parameters.
+    pub fn url_for(
+        &self,
+        name: &str,
+        params: &[(&str, &str)],
+    ) -> result::Result<String, RoutingError> {
+        let endpoint = self
+            .endpoints
+            .iter()
+            .find(|endpoint| endpoint.name() == Some(name))
+            .ok_or_else(|| RoutingError::UnknownRouteError(name.to_string()))?;
+        endpoint.pattern().build_path(name, params)
+    }
+
+    // Returns the endpoint matching the request's method, path and guards.
+    pub fn get_endpoint(&self, request: &HttpRequest) -> Result<&Endpoint> {
+        self.find_endpoint(request.method(), request)
+            .map(|(endpoint, _)| endpoint)
+            .ok_or(AlcazarError::HttpError(HttpError::InternalServerError))
+    }
+
+    // Returns the endpoint for the method that matches the request's path and guards,
+    // along with the values of the route parameters.
+    fn find_endpoint(
+        &self,
+        method: &MethodType,
+        request: &HttpRequest,
+    ) -> Option<(&Endpoint, Vec<(String, String)>)> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| endpoint.methods().contains(method))
+            .find_map(|endpoint| Some((endpoint, endpoint.matches(request)?)))
+    }
+
+    // Runs the endpoint matching the request and returns its response. HEAD and OPTIONS
+    // requests are answered automatically unless an endpoint declares those methods.
+    pub(crate) async fn dispatch(&self, mut request: HttpRequest) -> HttpResponse {
+        let method = request.method().clone();
+        if let Some((endpoint, params)) = self.find_endpoint(&method, &request) {
+            set_route(&mut request, endpoint, params);
+            return endpoint.pipeline(&self.middlewares).run(request).await;
+        }
+
+        let allowed_methods = self.allowed_methods(&request);
+        match method {
+            // Run the GET handler to get the real headers, but never send the body
+            MethodType::HEAD => {
+                if let Some((endpoint, params)) = self.find_endpoint(&MethodType::GET, &request) {
+                    set_route(&mut request, endpoint, params);
+                    let pipeline = endpoint.pipeline(&self.middlewares);
+                    let mut response = pipeline.run(request).await;
+                    response.strip_body();
+                    return response;
+                }
+            }
+            MethodType::OPTIONS if !allowed_methods.is_empty() => {
+                return HttpResponse::new(StatusCode::NoContent)
+                    .with_header("Allow", &allowed_methods.join(", "));
+            }
+            _ => {}
+        }
+
+        if allowed_methods.is_empty() {
+            HttpResponse::new(StatusCode::NotFound)
+        } else {
+            HttpResponse::new(StatusCode::MethodNotAllowed)
+                .with_header("Allow", &allowed_methods.join(", "))
+        }
+    }
+
+    // Returns the names of all methods that can be used for the request's path.
+    fn allowed_methods(&self, request: &HttpRequest) -> Vec<String> {
+        let mut methods: Vec<MethodType> = Vec::new();
+        let declared = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.matches(request).is_some())
+            .flat_map(|endpoint| endpoint.methods().iter().cloned());
+        for method in declared {
+            if !methods.contains(&method) {
+                methods.push(method);
+            }
+        }
+        if methods.is_empty() {
+            return Vec::new();
+        }
+
+        // HEAD and OPTIONS are always available through the automatic handling
+        if methods.contains(&MethodType::GET) && !methods.contains(&MethodType::HEAD) {
+            methods.push(MethodType::HEAD);
+        }
+        if !methods.contains(&MethodType::OPTIONS) {
+            methods.push(MethodType::OPTIONS);
+        }
+        methods.iter().map(MethodType::to_string).collect()
+    }
+}
+
+// Gives the request the parameters of the matched route, along with the name of its
+// wildcard and its constraints.
+fn set_route(request: &mut HttpRequest, endpoint: &Endpoint, params: Vec<(String, String)>) {
+    request.set_params(params);
+    let pattern = endpoint.pattern();
+    request.extensions_mut().insert(MatchedRoute {
+        wildcard: pattern.wildcard().map(str::to_string),
+        constraints: pattern
+            .constraints()
+            .into_iter()
+            .map(|(name, constraint)| (name.to_string(), constraint.clone()))
+            .collect(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::HttpRequest;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::routing::guard::{Header, Host};
+    use crate::routing::route::Route;
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+
+    async fn handler() -> StatusCode {
+        StatusCode::Ok
+    }
+
+    async fn hello() -> &'static str {
+        "Hello, world!"
+    }
+
+    fn dispatch(router: &Router, method: MethodType, path: &str) -> Vec<u8> {
+        let request = HttpRequest::new(method, path);
+        let response = run(router.dispatch(request), ProcStack::default());
+        response.into_bytes_response()
+    }
+
+    #[test]
+    fn test_register_put_and_extension_methods() {
+        let router = Router::new().with_endpoint("/files", &["put", "propfind"], handler);
+
+        let put = MethodType::PUT;
+        let propfind = MethodType::Extension("PROPFIND".to_string());
+        assert!(router
+            .get_endpoint(&HttpRequest::new(put, "/files"))
+            .is_ok());
+        assert!(router
+            .get_endpoint(&HttpRequest::new(propfind, "/files"))
+            .is_ok());
+        assert!(router
+            .get_endpoint(&HttpRequest::new(MethodType::GET, "/files"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_skip_endpoint_with_invalid_method() {
+        let router = Router::new().with_endpoint("/", &["get", "not a method"], handler);
+
+        assert!(router.endpoints().is_empty());
+    }
+
+    #[test]
+    fn test_answer_head_with_get_handler() {
+        let router = Router::new().with_endpoint("/", &["get"], hello);
+
+        let response = dispatch(&router, MethodType::HEAD, "/");
+        assert_eq!(
+            String::from_utf8(response).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: 13\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_answer_options_with_allowed_methods() {
+        let router = Router::new()
+            .with_endpoint("/users/{id}", &["get"], handler)
+            .with_endpoint("/users/{id}", &["put", "delete"], handler)
+            .with_endpoint("/users", &["post"], handler);
+
+        let response = dispatch(&router, MethodType::OPTIONS, "/users/1");
+        assert_eq!(
+            String::from_utf8(response).unwrap(),
+            "HTTP/1.1 204 No Content\r\nAllow: GET, PUT, DELETE, HEAD, OPTIONS\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_answer_unknown_path_and_method() {
+        let router = Router::new().with_endpoint("/", &["post"], handler);
+
+        let response = dispatch(&router, MethodType::OPTIONS, "/missing");
+        assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+
+        let response = dispatch(&router, MethodType::GET, "/");
+        assert!(
+            response.starts_with(b"HTTP/1.1 405 Method Not Allowed\r\nAllow: POST, OPTIONS\r\n")
+        );
+    }
+
+    #[test]
+    fn test_try_with_endpoint_returns_error() {
+        let result = Router::new().try_with_endpoint("/users/{id", &["get"], handler);
+        assert!(result.is_err());
+
+        let result = Router::new().try_with_endpoint("/users/{id}", &["get"], handler);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_build_reports_all_errors() {
+        let result = Router::new()
+            .with_endpoint("/users/{id}}", &["get"], handler)
+            .with_endpoint("/users", &["get", "post"], handler)
+            .with_endpoint("/users", &["post"], handler)
+            .with_endpoint("/users/{id}", &["get"], handler)
+            .with_endpoint("/users/me", &["get"], handler)
+            .build();
+
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "found invalid routes: \
+             found an invalid \"}\" part of the \"/users/{id}}\" path. \
+             the \"/users\" path is declared more than once for POST methods. \
+             the \"/users/me\" path overlaps with the \"/users/{id}\" path for GET methods."
+        );
+    }
+
+    #[test]
+    fn test_build_accepts_static_path_before_dynamic_one() {
+        let result = Router::new()
+            .with_endpoint("/users/me", &["get"], handler)
+            .with_endpoint("/users/{id}", &["get"], handler)
+            .with_endpoint("/users/{id}", &["delete"], handler)
+            .build();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_nest_router_under_prefix() {
+        let users = Router::new()
+            .with_endpoint("/", &["get"], handler)
+            .with_endpoint("/users/{id}", &["get"], hello);
+        let router = Router::new().nest("/api/{version}/", users);
+
+        assert!(router
+            .get_endpoint(&HttpRequest::new(MethodType::GET, "/api/v1"))
+            .is_ok());
+        assert!(router
+            .get_endpoint(&HttpRequest::new(MethodType::GET, "/api/v1/users/1"))
+            .is_ok());
+        assert!(router
+            .get_endpoint(&HttpRequest::new(MethodType::GET, "/users/1"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_scope_endpoints() {
+        let router = Router::new()
+            .with_endpoint("/", &["get"], handler)
+            .scope("/admin", |admin| {
+                admin
+                    .with_endpoint("/users", &["get"], handler)
+                    .with_endpoint("/posts", &["post"], handler)
+            });
+
+        assert!(router
+            .get_endpoint(&HttpRequest::new(MethodType::GET, "/admin/users"))
+            .is_ok());
+        assert!(router
+            .get_endpoint(&HttpRequest::new(MethodType::POST, "/admin/posts"))
+            .is_ok());
+        assert!(router.build().is_ok());
+    }
+
+    #[test]
+    fn test_nest_reports_invalid_prefix() {
+        let users = Router::new().with_endpoint("/users", &["get"], handler);
+        let result = Router::new().nest("/api/{version", users).build();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_constrained_route_falls_through() {
+        let router = Router::new()
+            .with_endpoint("/users/{id:int}", &["get"], handler)
+            .with_endpoint("/users/{name:alpha}", &["get"], hello);
+
+        let endpoint = router
+            .get_endpoint(&HttpRequest::new(MethodType::GET, "/users/abc"))
+            .unwrap();
+        assert_eq!(endpoint.path(), "/users/{name:alpha}");
+
+        let response = dispatch(&router, MethodType::GET, "/users/abc-1");
+        assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn test_build_url_for_named_route() {
+        let users = Router::new()
+            .with_route(Route::new("/users/{id:int}", &["get"], handler).name("user"))
+            .with_route(Route::new("/users", &["get"], handler).name("users"));
+        let router = Router::new().nest("/api/{version}", users);
+
+        let url = router.url_for("user", &[("version", "v1"), ("id", "7")]);
+        assert_eq!(url.unwrap(), "/api/v1/users/7");
+        assert_eq!(
+            router
+                .url_for("user", &[("version", "v1")])
+                .unwrap_err()
+                .to_string(),
+            "missing value for the \"id\" parameter of the \"user\" route."
+        );
+        assert_eq!(
+            router.url_for("post", &[]).unwrap_err().to_string(),
+            "there is no route named \"post\"."
+        );
+    }
+
+    #[test]
+    fn test_build_reports_duplicate_names() {
+        let result = Router::new()
+            .with_route(Route::new("/", &["get"], handler).name("home"))
+            .with_route(Route::new("/index", &["get"], handler).name("home"))
+            .build();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_route_guards() {
+        let host = Host::new("{tenant}.example.com").unwrap();
+        let router = Router::new()
+            .with_route(Route::new("/", &["get"], hello).guard(host))
+            .with_route(Route::new("/api", &["get"], hello).guard(Header::new("X-Version", "2")))
+            .with_endpoint("/api", &["get"], handler);
+
+        let request =
+            HttpRequest::new(MethodType::GET, "/").with_header("Host", "acme.example.com");
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.body(), b"Hello, world!");
+
+        let request = HttpRequest::new(MethodType::GET, "/").with_header("Host", "example.org");
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.status(), StatusCode::NotFound);
+
+        let request = HttpRequest::new(MethodType::GET, "/api").with_header("X-Version", "1");
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.body(), b"");
+
+        let request = HttpRequest::new(MethodType::GET, "/").with_header("Host", "example.org");
+        assert!(router.get_endpoint(&request).is_err());
+        let request =
+            HttpRequest::new(MethodType::GET, "/").with_header("Host", "acme.example.com");
+        assert!(router.get_endpoint(&request).is_ok());
+        assert!(router.build().is_ok());
     }
 }
@@ -1,34 +1,132 @@
-use crate::routing::pattern::PatternType;
+use crate::routing::{guard::Guard, pattern::PatternType};
 use crate::{
-    error::{AlcazarError, HttpError, Result},
-    status_code::StatusCode,
+    error::{AlcazarError, ParseError, Result, RoutingError},
+    handler::Handler,
+    middleware::{Middleware, Next},
+    request::HttpRequest,
+    response::HttpResponse,
 };
-use futures::future::{FutureExt, FutureObj, Shared};
-use std::{future::Future, str::FromStr};
+use futures::future::BoxFuture;
+use std::{fmt, result, str::FromStr, sync::Arc};
+
+pub(crate) type BoxHandler =
+    Arc<dyn Fn(HttpRequest) -> BoxFuture<'static, HttpResponse> + Send + Sync>;
+
+// Wraps the handler, so it can be stored whatever its arguments and output type are.
+pub(crate) fn into_handler<H, Args>(handler: H) -> BoxHandler
+where
+    H: Handler<Args>,
+{
+    let handler = Arc::new(handler);
+    Arc::new(move |request| handler.clone().call(request))
+}
 
 // TODO: Replace String in path for the 'a str type
 // TODO: Mark the structure and methods as pub(crate) later
 #[derive(Clone)]
 pub struct Endpoint {
+    path: String,
     pattern: PatternType,
     methods: Vec<MethodType>,
-    handler: Shared<FutureObj<'static, StatusCode>>,
+    handler: BoxHandler,
+    name: Option<String>,
+    guards: Vec<Arc<dyn Guard>>,
+    middlewares: Vec<Arc<dyn Middleware>>,
 }
 
 impl Endpoint {
     // Returns a default initialized endpoint instance.
-    pub fn new<C, F>(path: &str, methods: Vec<MethodType>, handler: C) -> Result<Self>
+    pub fn new<H, Args>(path: &str, methods: Vec<MethodType>, handler: H) -> Result<Self>
     where
-        C: Fn() -> F + Send + 'static,
-        F: Future<Output = StatusCode> + Send + 'static,
+        H: Handler<Args>,
     {
         let pattern = PatternType::from_str(path)?;
-        let handler = FutureObj::new(Box::new(handler())).shared();
-        Ok(Endpoint {
+        Ok(Endpoint::with_pattern(
+            path,
+            pattern,
+            methods,
+            into_handler(handler),
+        ))
+    }
+
+    // Returns an endpoint for the already parsed pattern of the path.
+    pub(crate) fn with_pattern(
+        path: &str,
+        pattern: PatternType,
+        methods: Vec<MethodType>,
+        handler: BoxHandler,
+    ) -> Self {
+        Endpoint {
+            path: path.to_string(),
             pattern,
             methods,
             handler,
-        })
+            name: None,
+            guards: Vec::new(),
+            middlewares: Vec::new(),
+        }
+    }
+
+    pub(crate) fn set_name(&mut self, name: Option<String>) {
+        self.name = name;
+    }
+
+    pub(crate) fn set_guards(&mut self, guards: Vec<Arc<dyn Guard>>) {
+        self.guards = guards;
+    }
+
+    pub(crate) fn set_middlewares(&mut self, middlewares: Vec<Arc<dyn Middleware>>) {
+        self.middlewares = middlewares;
+    }
+
+    // Runs the given middleware before the ones the endpoint already has, used
+    // when the endpoint is taken from a router with its own middleware.
+    pub(crate) fn wrap(&mut self, middlewares: &[Arc<dyn Middleware>]) {
+        let mut wrapped = middlewares.to_vec();
+        wrapped.append(&mut self.middlewares);
+        self.middlewares = wrapped;
+    }
+
+    pub(crate) fn has_guards(&self) -> bool {
+        !self.guards.is_empty()
+    }
+
+    // Checks the path and the guards against the request, returning the values of the
+    // route parameters when all of them match.
+    pub(crate) fn matches(&self, request: &HttpRequest) -> Option<Vec<(String, String)>> {
+        let mut params = self.pattern.params(request.path())?;
+        for guard in &self.guards {
+            if !guard.check(request, &mut params) {
+                return None;
+            }
+        }
+        Some(params)
+    }
+
+    // Returns a copy of the endpoint mounted under the given path prefix.
+    pub(crate) fn with_prefix(&self, prefix: &str) -> result::Result<Self, RoutingError> {
+        let prefix = prefix.trim_end_matches('/');
+        let path = match self.path.as_str() {
+            "" | "/" if prefix.is_empty() => "/".to_string(),
+            "" | "/" => prefix.to_string(),
+            path if path.starts_with('/') => format!("{}{}", prefix, path),
+            path => format!("{}/{}", prefix, path),
+        };
+
+        let mut endpoint = self.clone();
+        endpoint.pattern = PatternType::parse(&path)?;
+        endpoint.path = path;
+        Ok(endpoint)
+    }
+
+    // Returns the path the endpoint was declared with.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Returns the name the endpoint can be referred to by.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
     }
 
     // Returns a pattern against which can be checked match.
@@ -41,22 +139,53 @@ impl Endpoint {
         &self.methods
     }
 
-    pub fn handler(&self) -> Shared<FutureObj<'static, StatusCode>> {
-        self.handler.clone()
+    // Returns the pipeline running the given middleware, then the endpoint's
+    // middleware and finally the handler.
+    pub(crate) fn pipeline(&self, middlewares: &[Arc<dyn Middleware>]) -> Next {
+        let mut pipeline = middlewares.to_vec();
+        pipeline.extend(self.middlewares.iter().cloned());
+        Next::new(pipeline, self.handler.clone())
     }
 }
 
 // TODO: Mark the enum as pub(crate) later
-#[derive(PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum MethodType {
     POST,
     GET,
+    PUT,
     PATCH,
     DELETE,
     CONNECT,
     OPTIONS,
     TRACE,
     HEAD,
+    // Any other method that is a valid token (e.g. WebDAV's PROPFIND)
+    Extension(String),
+}
+
+impl MethodType {
+    // Returns the method name as it appears in the request line.
+    pub fn as_str(&self) -> &str {
+        match self {
+            MethodType::POST => "POST",
+            MethodType::GET => "GET",
+            MethodType::PUT => "PUT",
+            MethodType::PATCH => "PATCH",
+            MethodType::DELETE => "DELETE",
+            MethodType::CONNECT => "CONNECT",
+            MethodType::OPTIONS => "OPTIONS",
+            MethodType::TRACE => "TRACE",
+            MethodType::HEAD => "HEAD",
+            MethodType::Extension(method) => method.as_str(),
+        }
+    }
+}
+
+impl fmt::Display for MethodType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 impl FromStr for MethodType {
@@ -66,13 +195,56 @@ impl FromStr for MethodType {
         match method {
             "POST" => Ok(MethodType::POST),
             "GET" => Ok(MethodType::GET),
+            "PUT" => Ok(MethodType::PUT),
             "PATCH" => Ok(MethodType::PATCH),
             "DELETE" => Ok(MethodType::DELETE),
             "CONNECT" => Ok(MethodType::CONNECT),
             "OPTIONS" => Ok(MethodType::OPTIONS),
             "TRACE" => Ok(MethodType::TRACE),
             "HEAD" => Ok(MethodType::HEAD),
-            _ => Err(AlcazarError::HttpError(HttpError::MethodNotImplemented)),
+            _ if is_token(method) => Ok(MethodType::Extension(method.to_string())),
+            _ => Err(AlcazarError::ParseError(ParseError::InvalidMethod(
+                method.to_string(),
+            ))),
         }
     }
 }
+
+// Checks that the method name is a token as defined in RFC 7230, section 3.2.6
+fn is_token(method: &str) -> bool {
+    !method.is_empty()
+        && method
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routing::endpoint::MethodType;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_standard_methods() {
+        assert_eq!(MethodType::from_str("PUT").unwrap(), MethodType::PUT);
+        assert_eq!(MethodType::from_str("GET").unwrap(), MethodType::GET);
+    }
+
+    #[test]
+    fn test_parse_extension_method() {
+        let method = MethodType::from_str("PROPFIND").unwrap();
+        assert_eq!(method, MethodType::Extension("PROPFIND".to_string()));
+        assert_eq!(method.to_string(), "PROPFIND");
+    }
+
+    #[test]
+    fn test_get_error_for_invalid_method() {
+        let result = MethodType::from_str("GET /");
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "\"GET /\" is not a valid method name"
+        );
+
+        assert!(MethodType::from_str("").is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::error::RoutingError;
+use crate::request::HttpRequest;
+use regex::{escape, Regex};
+use std::result;
+
+// Additional condition checked for the request, once the method and the path
+// of an endpoint have matched. Guards may capture values from the request,
+// which are then available as route parameters.
+pub trait Guard: Send + Sync + 'static {
+    fn check(&self, request: &HttpRequest, captures: &mut Vec<(String, String)>) -> bool;
+}
+
+impl<F> Guard for F
+where
+    F: Fn(&HttpRequest) -> bool + Send + Sync + 'static,
+{
+    fn check(&self, request: &HttpRequest, _captures: &mut Vec<(String, String)>) -> bool {
+        self(request)
+    }
+}
+
+// Matches the `Host` header against a pattern, where `*` stands for any label
+// and `{name}` captures one (e.g. `{tenant}.example.com`). The port is ignored
+// unless the pattern declares one.
+#[derive(Debug, Clone)]
+pub struct Host {
+    regex: Regex,
+    with_port: bool,
+}
+
+impl Host {
+    pub fn new(pattern: &str) -> result::Result<Self, RoutingError> {
+        let invalid_part = |part: &str| RoutingError::InvalidHostError {
+            part: part.to_string(),
+            host: pattern.to_string(),
+        };
+
+        let mut regex_pattern = String::new();
+        for label in pattern.split('.') {
+            if !regex_pattern.is_empty() {
+                regex_pattern.push_str(r"\.");
+            }
+            match label {
+                "*" => regex_pattern.push_str(r"[^.]+"),
+                _ if label.starts_with('{') && label.ends_with('}') => {
+                    let name = &label[1..label.len() - 1];
+                    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                        return Err(invalid_part(label));
+                    }
+                    regex_pattern.push_str(&format!(r"(?P<{}>[^.]+)", name));
+                }
+                _ if label.is_empty() || label.contains(['{', '}', '*']) => {
+                    return Err(invalid_part(label));
+                }
+                _ => regex_pattern.push_str(&escape(label)),
+            }
+        }
+
+        match Regex::new(&format!("(?i)^{}$", regex_pattern)) {
+            Ok(regex) => Ok(Host {
+                regex,
+                with_port: pattern.contains(':'),
+            }),
+            Err(err) => Err(RoutingError::RegexCompileError(err.to_string())),
+        }
+    }
+}
+
+impl Guard for Host {
+    fn check(&self, request: &HttpRequest, captures: &mut Vec<(String, String)>) -> bool {
+        let host = match request.header("Host") {
+            Some(host) => host.trim(),
+            None => return false,
+        };
+        let host = match host.rfind(':') {
+            // Keep IPv6 addresses like `[::1]` whole
+            Some(index) if !self.with_port && !host[index..].contains(']') => &host[..index],
+            _ => host,
+        };
+
+        let matched = match self.regex.captures(host) {
+            Some(matched) => matched,
+            None => return false,
+        };
+        for name in self.regex.capture_names().flatten() {
+            if let Some(value) = matched.name(name) {
+                captures.push((name.to_string(), value.as_str().to_string()));
+            }
+        }
+        true
+    }
+}
+
+// Requires the header to be present, optionally with the exact value.
+#[derive(Debug, Clone)]
+pub struct Header {
+    name: String,
+    value: Option<String>,
+}
+
+impl Header {
+    pub fn new(name: &str, value: &str) -> Self {
+        Header {
+            name: name.to_string(),
+            value: Some(value.to_string()),
+        }
+    }
+
+    pub fn exists(name: &str) -> Self {
+        Header {
+            name: name.to_string(),
+            value: None,
+        }
+    }
+}
+
+impl Guard for Header {
+    fn check(&self, request: &HttpRequest, _captures: &mut Vec<(String, String)>) -> bool {
+        let mut values = request.headers().get_all(&self.name).peekable();
+        match &self.value {
+            Some(expected) => values.any(|value| value.trim() == expected),
+            None => values.peek().is_some(),
+        }
+    }
+}
+
+// Matches the media type of the request body, ignoring its parameters, so
+// `application/json` accepts `application/json; charset=utf-8`.
+#[derive(Debug, Clone)]
+pub struct ContentType {
+    media_type: String,
+}
+
+impl ContentType {
+    pub fn new(media_type: &str) -> Self {
+        ContentType {
+            media_type: media_type.to_string(),
+        }
+    }
+}
+
+impl Guard for ContentType {
+    fn check(&self, request: &HttpRequest, _captures: &mut Vec<(String, String)>) -> bool {
+        match request.media_type() {
+            Some(media_type) => media_type.eq_ignore_ascii_case(&self.media_type),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::HttpRequest;
+    use crate::routing::endpoint::MethodType;
+    use crate::routing::guard::{ContentType, Guard, Header, Host};
+
+    fn request_with_header(name: &str, value: &str) -> HttpRequest {
+        HttpRequest::new(MethodType::GET, "/").with_header(name, value)
+    }
+
+    #[test]
+    fn test_host_guard_captures_subdomain() {
+        let guard = Host::new("{tenant}.example.com").unwrap();
+        let mut captures = Vec::new();
+
+        let request = request_with_header("Host", "acme.Example.com:8080");
+        assert!(guard.check(&request, &mut captures));
+        assert_eq!(captures, [("tenant".to_string(), "acme".to_string())]);
+
+        let request = request_with_header("Host", "a.b.example.com");
+        assert!(!guard.check(&request, &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_host_guard_with_wildcard() {
+        let guard = Host::new("*.example.com").unwrap();
+
+        let request = request_with_header("Host", "api.example.com");
+        assert!(guard.check(&request, &mut Vec::new()));
+        let request = request_with_header("Host", "example.com");
+        assert!(!guard.check(&request, &mut Vec::new()));
+        assert!(Host::new("api.{tenant.example.com").is_err());
+    }
+
+    #[test]
+    fn test_header_and_content_type_guards() {
+        let request = request_with_header("Content-Type", "application/json; charset=utf-8")
+            .with_header("Accept-Version", "2");
+
+        assert!(ContentType::new("application/json").check(&request, &mut Vec::new()));
+        assert!(Header::new("accept-version", "2").check(&request, &mut Vec::new()));
+        assert!(!Header::new("Accept-Version", "1").check(&request, &mut Vec::new()));
+        assert!(!Header::exists("Authorization").check(&request, &mut Vec::new()));
+    }
+}
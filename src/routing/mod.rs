@@ -1,4 +1,9 @@
 pub(crate) mod endpoint;
+pub mod guard;
 pub(crate) mod pattern;
+pub(crate) mod route;
 
 pub use crate::routing::endpoint::{Endpoint, MethodType};
+pub use crate::routing::guard::Guard;
+pub use crate::routing::pattern::{Constraint, ParamKind, PathPart, PatternType};
+pub use crate::routing::route::Route;
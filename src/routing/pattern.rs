@@ -1,28 +1,178 @@
 use crate::error::RoutingError::RegexCompileError;
 use crate::error::{AlcazarError, Result, RoutingError};
 use lazy_static::lazy_static;
-use regex::{escape, CaptureMatches, Captures, Regex};
-use std::str::FromStr;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::{escape, Regex};
+use std::{result, str::FromStr};
 
 lazy_static! {
     static ref ANY_VALUE_REGEX: Regex = Regex::new(r"[^{}/]+").unwrap();
-    static ref DYN_PARAM_REGEX: Regex = Regex::new(r"(\{\s*[\w\d_]+\s*\})").unwrap();
-    static ref VALID_DYN_PARAM_REGEX: Regex = Regex::new(r"\{(?P<part>[\w][\w\d_]*)\}").unwrap();
+    static ref TAIL_VALUE_REGEX: Regex = Regex::new(r".*").unwrap();
+    static ref VALID_DYN_PARAM_REGEX: Regex = Regex::new(
+        r"(?s)^(?P<wildcard>\*)?(?P<part>[\w][\w\d_]*)(?P<optional>\?)?(?::(?P<constraint>.+))?$"
+    )
+    .unwrap();
+    static ref CONSTRAINT_NAME_REGEX: Regex = Regex::new(r"^[a-z]+$").unwrap();
 }
 
+// Characters that are percent-encoded in the path segments built from parameters,
+// that is everything except the unreserved ones
+const SEGMENT_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
 #[derive(Debug, Clone)]
 pub enum PatternType {
     Static(String),
-    Dynamic(Regex),
+    // The regex that matches the whole path and the parts it was built from
+    Dynamic(Regex, Vec<PathPart>),
 }
 
 impl PatternType {
     // Checks that the given path matches against the regex or static path
+    #[cfg(test)]
     pub(crate) fn is_match(&self, path: &str) -> bool {
         match self {
             PatternType::Static(string) => string == path,
-            PatternType::Dynamic(regex) => regex.is_match(path),
+            PatternType::Dynamic(regex, _) => regex.is_match(path),
+        }
+    }
+
+    // Returns the values of the dynamic parts for the matching path. Optional parts
+    // and wildcards that didn't match anything are left out.
+    pub fn params(&self, path: &str) -> Option<Vec<(String, String)>> {
+        match self {
+            PatternType::Static(string) if string == path => Some(Vec::new()),
+            PatternType::Static(_) => None,
+            PatternType::Dynamic(regex, _) => {
+                let captures = regex.captures(path)?;
+                let params = regex
+                    .capture_names()
+                    .flatten()
+                    .filter_map(|name| {
+                        let value = captures.name(name)?;
+                        Some((name.to_string(), value.as_str().to_string()))
+                    })
+                    .collect();
+                Some(params)
+            }
+        }
+    }
+
+    // Returns the name of the wildcard parameter, e.g. `path` for `/files/{*path}`.
+    pub fn wildcard(&self) -> Option<&str> {
+        let parts = match self {
+            PatternType::Static(_) => return None,
+            PatternType::Dynamic(_, parts) => parts,
+        };
+        parts.iter().find_map(|part| match part {
+            PathPart::Param {
+                name,
+                kind: ParamKind::Wildcard,
+                ..
+            } => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    // Returns the constrained parameters with their constraint, e.g. `id` and
+    // `Constraint::Int` for `/users/{id:int}`.
+    pub fn constraints(&self) -> Vec<(&str, &Constraint)> {
+        let parts = match self {
+            PatternType::Static(_) => return Vec::new(),
+            PatternType::Dynamic(_, parts) => parts,
+        };
+        parts
+            .iter()
+            .filter_map(|part| match part {
+                PathPart::Param {
+                    name,
+                    constraint: Some(constraint),
+                    ..
+                } => Some((name.as_str(), constraint)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// Parameter metadata of the route matched by the request, kept in the request's
+// extensions: the name of its wildcard, as the parameter is left out when the path
+// omits it, and the constraints the values were matched against.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MatchedRoute {
+    pub(crate) wildcard: Option<String>,
+    pub(crate) constraints: Vec<(String, Constraint)>,
+}
+
+impl PatternType {
+    // Builds a path matching the pattern of the named route from the given parameter values,
+    // which are percent-encoded. Fails on missing values or values violating the constraints.
+    pub(crate) fn build_path(
+        &self,
+        route: &str,
+        params: &[(&str, &str)],
+    ) -> result::Result<String, RoutingError> {
+        let parts = match self {
+            PatternType::Static(path) => return Ok(path.clone()),
+            PatternType::Dynamic(_, parts) => parts,
+        };
+        let find_value = |name: &str| {
+            params
+                .iter()
+                .find(|(param_name, _)| *param_name == name)
+                .map(|(_, value)| *value)
+        };
+
+        let mut built_path = String::new();
+        for part in parts {
+            let (name, kind, constraint) = match part {
+                PathPart::Static(part) => {
+                    built_path.push_str(part);
+                    continue;
+                }
+                PathPart::Param {
+                    name,
+                    kind,
+                    constraint,
+                } => (name, *kind, constraint),
+            };
+
+            let value = match (find_value(name), kind) {
+                (Some(value), ParamKind::Wildcard) => value
+                    .split('/')
+                    .map(|segment| utf8_percent_encode(segment, SEGMENT_ENCODE_SET).to_string())
+                    .collect::<Vec<String>>()
+                    .join("/"),
+                (Some(value), _) => utf8_percent_encode(value, SEGMENT_ENCODE_SET).to_string(),
+                // Omitted optional parts take the preceding slash away as well
+                (None, ParamKind::Optional) | (None, ParamKind::Wildcard) => {
+                    if built_path.ends_with('/') {
+                        built_path.pop();
+                    }
+                    continue;
+                }
+                (None, ParamKind::Single) => {
+                    return Err(RoutingError::MissingParamError {
+                        param: name.clone(),
+                        route: route.to_string(),
+                    })
+                }
+            };
+
+            let value_regex = Regex::new(&format!("^{}$", value_regex(kind, constraint)));
+            if !value_regex.is_ok_and(|regex| regex.is_match(&value)) {
+                return Err(RoutingError::InvalidParamError {
+                    param: name.clone(),
+                    value,
+                    route: route.to_string(),
+                });
+            }
+            built_path.push_str(&value);
         }
+        Ok(built_path)
     }
 }
 
@@ -30,116 +180,485 @@ impl FromStr for PatternType {
     type Err = AlcazarError;
 
     fn from_str(path: &str) -> Result<PatternType> {
+        Ok(PatternType::parse(path)?)
+    }
+}
+
+impl PatternType {
+    pub(crate) fn parse(path: &str) -> result::Result<PatternType, RoutingError> {
+        let parts = split_parts(path)?;
         // URL with dynamic parts must be wrapped in curly braces
-        if path.contains('{') && path.contains('}') {
-            let mut pattern = String::new();
+        if parts.iter().all(|part| matches!(part, PathPart::Static(_))) {
+            return Ok(PatternType::Static(path.to_string()));
+        }
 
-            for state in SplitCaptures::new(&DYN_PARAM_REGEX, path) {
-                let raw_part = match state {
-                    SplitState::Unmatched(unmatched_part) => unmatched_part.to_string(),
-                    SplitState::Captured(capture) => capture[0].to_string(),
-                };
+        let regex = compile_regex(&parts)?;
+        Ok(PatternType::Dynamic(regex, parts))
+    }
+}
 
-                match VALID_DYN_PARAM_REGEX.captures(&raw_part) {
-                    // Construct a regular expression for the dynamic part
-                    Some(capture) => {
-                        let regex_part =
-                            format!("(?P<{}>{})", &capture["part"], &ANY_VALUE_REGEX.as_str());
-                        pattern.push_str(&regex_part);
-                    }
-                    // Use static parts as-is
-                    _ => {
-                        if raw_part.contains('{') || raw_part.contains('}') {
-                            return Err(AlcazarError::RoutingError(
-                                RoutingError::InvalidPathError {
-                                    part: raw_part,
-                                    path: path.to_string(),
-                                },
-                            ));
-                        }
-
-                        let regex_part = escape(&raw_part);
-                        pattern.push_str(&regex_part);
-                    }
-                };
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamKind {
+    // `{name}` matches a non-empty part of a single segment
+    Single,
+    // `{name?}` matches a whole segment that can be omitted along with its slash
+    Optional,
+    // `{*name}` matches the rest of the path, including slashes
+    Wildcard,
+}
+
+// Restriction on the values of a parameter, declared after a colon (e.g. `{id:int}`).
+// Requests with values that don't satisfy it fall through to the next routes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+    // `int`: an optionally negative integer, like `-42`
+    Int,
+    // `uint`: a non-negative integer, like `42`
+    Uint,
+    // `float`: a decimal number, like `-4.2`
+    Float,
+    // `alpha`: ASCII letters only
+    Alpha,
+    // `uuid`: an UUID in its hyphenated form
+    Uuid,
+    // Any other regular expression, like `{slug:[a-z-]+}`
+    Regex(String),
+}
+
+impl Constraint {
+    // Returns the regular expression the value must match.
+    pub fn regex(&self) -> &str {
+        match self {
+            Constraint::Int => r"-?[0-9]+",
+            Constraint::Uint => r"[0-9]+",
+            Constraint::Float => r"-?[0-9]+(?:\.[0-9]+)?",
+            Constraint::Alpha => r"[a-zA-Z]+",
+            Constraint::Uuid => {
+                r"[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}"
             }
+            Constraint::Regex(regex) => regex.as_str(),
+        }
+    }
+
+    fn parse(constraint: &str) -> Option<Constraint> {
+        match constraint {
+            "int" => Some(Constraint::Int),
+            "uint" => Some(Constraint::Uint),
+            "float" => Some(Constraint::Float),
+            "alpha" => Some(Constraint::Alpha),
+            "uuid" => Some(Constraint::Uuid),
+            // Plain words are most likely misspelled constraint names
+            _ if CONSTRAINT_NAME_REGEX.is_match(constraint) => None,
+            _ => Some(Constraint::Regex(constraint.to_string())),
+        }
+    }
+
+    // Describes whether the values of the first constraint are a subset of the second one.
+    fn relation(first: &Option<Constraint>, second: &Option<Constraint>) -> Relation {
+        use Constraint::*;
+
+        match (first, second) {
+            (first, second) if first == second => Relation::SAME,
+            (_, None) => Relation::NARROWER,
+            (None, _) => Relation::WIDER,
+            (Some(Uint), Some(Int)) | (Some(Uint), Some(Float)) | (Some(Int), Some(Float)) => {
+                Relation::NARROWER
+            }
+            (Some(Int), Some(Uint)) | (Some(Float), Some(Uint)) | (Some(Float), Some(Int)) => {
+                Relation::WIDER
+            }
+            (Some(Regex(_)), _) | (_, Some(Regex(_))) => Relation::AMBIGUOUS,
+            // Letters, numbers and UUIDs never match each other
+            _ => Relation::DISJOINT,
+        }
+    }
+}
 
-            // Compile the whole regular expression that matches to the path
-            let regex_pattern = match Regex::new(&pattern) {
-                Ok(regex) => regex,
-                Err(err) => {
-                    return Err(AlcazarError::RoutingError(RegexCompileError(
-                        err.to_string(),
-                    )))
+#[derive(Debug, Clone)]
+pub enum PathPart {
+    Static(String),
+    Param {
+        name: String,
+        kind: ParamKind,
+        constraint: Option<Constraint>,
+    },
+}
+
+// Splits the path into static parts and parameters wrapped in curly braces.
+fn split_parts(path: &str) -> result::Result<Vec<PathPart>, RoutingError> {
+    let invalid_part = |part: &str| RoutingError::InvalidPathError {
+        part: part.to_string(),
+        path: path.to_string(),
+    };
+
+    let mut parts = Vec::new();
+    let mut rest = path;
+    while !rest.is_empty() {
+        if !rest.starts_with('{') {
+            let end = rest.find('{').unwrap_or(rest.len());
+            let raw_part = &rest[..end];
+            if raw_part.contains('}') {
+                return Err(invalid_part(raw_part));
+            }
+            parts.push(PathPart::Static(raw_part.to_string()));
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = closing_brace(rest).ok_or_else(|| invalid_part(rest))?;
+        let raw_part = &rest[..=end];
+        let capture = VALID_DYN_PARAM_REGEX
+            .captures(&raw_part[1..end])
+            .ok_or_else(|| invalid_part(raw_part))?;
+        let kind = match (capture.name("wildcard"), capture.name("optional")) {
+            (None, None) => ParamKind::Single,
+            (None, Some(_)) => ParamKind::Optional,
+            (Some(_), None) => ParamKind::Wildcard,
+            (Some(_), Some(_)) => return Err(invalid_part(raw_part)),
+        };
+        let constraint = match capture.name("constraint") {
+            Some(constraint) => match Constraint::parse(constraint.as_str()) {
+                Some(constraint) => Some(constraint),
+                None => {
+                    return Err(RoutingError::InvalidConstraintError {
+                        constraint: constraint.as_str().to_string(),
+                        path: path.to_string(),
+                    })
                 }
-            };
-            return Ok(PatternType::Dynamic(regex_pattern));
+            },
+            None => None,
+        };
+        if let Some(Constraint::Regex(regex)) = &constraint {
+            if let Err(err) = Regex::new(regex) {
+                return Err(RegexCompileError(err.to_string()));
+            }
         }
+        rest = &rest[end + 1..];
 
-        Ok(PatternType::Static(path.to_string()))
+        // Optional parts and wildcards must take the whole segment, and wildcards
+        // can only be used at the end of the path
+        let starts_segment = match parts.last() {
+            Some(PathPart::Static(part)) => part.ends_with('/'),
+            Some(PathPart::Param { .. }) => false,
+            None => true,
+        };
+        let ends_segment = match kind {
+            ParamKind::Single => true,
+            ParamKind::Optional => rest.is_empty() || rest.starts_with('/'),
+            ParamKind::Wildcard => rest.is_empty(),
+        };
+        if kind != ParamKind::Single && !(starts_segment && ends_segment) {
+            return Err(invalid_part(raw_part));
+        }
+
+        parts.push(PathPart::Param {
+            name: capture["part"].to_string(),
+            kind,
+            constraint,
+        });
     }
+    Ok(parts)
 }
 
-// Special wrapper around regex splits that handles matches / unmatched parts together.
-// For more info check the GitHub issue: https://github.com/rust-lang/regex/issues/330
-//
-// TODO: Replace this code when the functionality will be added to the crate
-// GitHub issue: https://github.com/rust-lang/regex/issues/681
-struct SplitCaptures<'r, 't> {
-    finder: CaptureMatches<'r, 't>,
-    text: &'t str,
-    last: usize,
-    caps: Option<Captures<'t>>,
+// Returns the position of the brace closing the one the text starts with.
+fn closing_brace(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, character) in text.char_indices() {
+        match character {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
 }
 
-impl<'r, 't> SplitCaptures<'r, 't> {
-    fn new(re: &'r Regex, text: &'t str) -> SplitCaptures<'r, 't> {
-        SplitCaptures {
-            finder: re.captures_iter(text),
-            text,
-            last: 0,
-            caps: None,
+// Compiles the regular expression that matches to the whole path.
+fn compile_regex(parts: &[PathPart]) -> result::Result<Regex, RoutingError> {
+    let mut pattern = String::new();
+    for part in parts {
+        match part {
+            // Use static parts as-is
+            PathPart::Static(part) => pattern.push_str(&escape(part)),
+            // Construct a regular expression for the dynamic part
+            PathPart::Param {
+                name,
+                kind: ParamKind::Single,
+                constraint,
+            } => {
+                let value_regex = value_regex(ParamKind::Single, constraint);
+                let regex_part = format!("(?P<{}>{})", name, value_regex);
+                pattern.push_str(&regex_part);
+            }
+            // The slash before an optional segment can be omitted too, so `/files/{*path}`
+            // matches `/files` as well as `/files/images/logo.png`
+            PathPart::Param {
+                name,
+                kind,
+                constraint,
+            } => {
+                let value_regex = value_regex(*kind, constraint);
+                let regex_part = if pattern.ends_with('/') {
+                    pattern.pop();
+                    format!("(?:/(?P<{}>{}))?", name, value_regex)
+                } else {
+                    format!("(?P<{}>{})?", name, value_regex)
+                };
+                pattern.push_str(&regex_part);
+            }
         }
     }
+
+    match Regex::new(&format!("^{}$", pattern)) {
+        Ok(regex) => Ok(regex),
+        Err(err) => Err(RegexCompileError(err.to_string())),
+    }
+}
+
+// Returns the regular expression for values of the parameter.
+fn value_regex(kind: ParamKind, constraint: &Option<Constraint>) -> String {
+    match (kind, constraint) {
+        (_, Some(constraint)) => format!("(?:{})", constraint.regex()),
+        (ParamKind::Wildcard, None) => TAIL_VALUE_REGEX.as_str().to_string(),
+        (_, None) => ANY_VALUE_REGEX.as_str().to_string(),
+    }
+}
+
+// How an earlier declared pattern relates to a later one with the same method.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Overlap {
+    // No path can be matched by both patterns
+    Disjoint,
+    // Both patterns match exactly the same paths
+    Same,
+    // The earlier pattern matches only a subset of the later one, so it takes precedence
+    Narrower,
+    // The earlier pattern matches everything the later one does, which is unreachable
+    Wider,
+    // Both patterns match some common paths and the registration order decides the winner
+    Ambiguous,
+}
+
+// Compares two paths segment by segment to find out whether they can match the same URLs.
+pub(crate) fn overlap(earlier: &str, later: &str) -> Overlap {
+    let (earlier_variants, later_variants) = match (segments(earlier), segments(later)) {
+        (Ok(earlier), Ok(later)) => (expand_optional(&earlier), expand_optional(&later)),
+        _ => return Overlap::Disjoint,
+    };
+
+    // Every variant of a pattern without optional segments is compared with every
+    // variant of the other one; a pattern is a subset if all its variants are.
+    let relations: Vec<Vec<Relation>> = earlier_variants
+        .iter()
+        .map(|left| {
+            later_variants
+                .iter()
+                .map(|right| compare_segments(left, right))
+                .collect()
+        })
+        .collect();
+    let intersects = relations
+        .iter()
+        .flatten()
+        .any(|relation| relation.intersects);
+    let narrower = relations
+        .iter()
+        .all(|row| row.iter().any(|relation| relation.narrower));
+    let wider =
+        (0..later_variants.len()).all(|column| relations.iter().any(|row| row[column].wider));
+
+    match (intersects, narrower, wider) {
+        (false, _, _) => Overlap::Disjoint,
+        (true, true, true) => Overlap::Same,
+        (true, true, false) => Overlap::Narrower,
+        (true, false, true) => Overlap::Wider,
+        (true, false, false) => Overlap::Ambiguous,
+    }
 }
 
-#[derive(Debug)]
-enum SplitState<'t> {
-    Unmatched(&'t str),
-    Captured(Captures<'t>),
+#[derive(Debug, Clone)]
+enum Segment {
+    Literal(String),
+    // A segment with a single parameter
+    Param(Option<Constraint>),
+    // A segment mixing static and dynamic parts, along with its shape that ignores
+    // parameter names
+    Dynamic(Regex, String),
+    Optional(Option<Constraint>),
+    Rest,
+}
+
+// Relation between two sets of paths: whether they have common paths and whether
+// the first one is a subset (narrower) or a superset (wider) of the other.
+#[derive(Debug, Clone, Copy)]
+struct Relation {
+    intersects: bool,
+    narrower: bool,
+    wider: bool,
 }
 
-impl<'r, 't> Iterator for SplitCaptures<'r, 't> {
-    type Item = SplitState<'t>;
+impl Relation {
+    const DISJOINT: Relation = Relation::new(false, false, false);
+    const SAME: Relation = Relation::new(true, true, true);
+    const NARROWER: Relation = Relation::new(true, true, false);
+    const WIDER: Relation = Relation::new(true, false, true);
+    const AMBIGUOUS: Relation = Relation::new(true, false, false);
 
-    fn next(&mut self) -> Option<SplitState<'t>> {
-        if let Some(caps) = self.caps.take() {
-            return Some(SplitState::Captured(caps));
+    const fn new(intersects: bool, narrower: bool, wider: bool) -> Self {
+        Relation {
+            intersects,
+            narrower,
+            wider,
         }
-        match self.finder.next() {
-            None => {
-                if self.last >= self.text.len() {
-                    None
-                } else {
-                    let s = &self.text[self.last..];
-                    self.last = self.text.len();
-                    Some(SplitState::Unmatched(s))
+    }
+}
+
+// Groups the parts of the path by segments.
+fn segments(path: &str) -> result::Result<Vec<Segment>, RoutingError> {
+    let mut grouped: Vec<Vec<PathPart>> = vec![Vec::new()];
+    for part in split_parts(path)? {
+        match part {
+            PathPart::Static(part) => {
+                for (index, piece) in part.split('/').enumerate() {
+                    if index > 0 {
+                        grouped.push(Vec::new());
+                    }
+                    if !piece.is_empty() {
+                        grouped
+                            .last_mut()
+                            .unwrap()
+                            .push(PathPart::Static(piece.to_string()));
+                    }
+                }
+            }
+            param => grouped.last_mut().unwrap().push(param),
+        }
+    }
+
+    grouped
+        .into_iter()
+        .map(|parts| match parts.as_slice() {
+            [] => Ok(Segment::Literal(String::new())),
+            [PathPart::Static(part)] => Ok(Segment::Literal(part.clone())),
+            [PathPart::Param {
+                kind: ParamKind::Wildcard,
+                ..
+            }] => Ok(Segment::Rest),
+            [PathPart::Param {
+                kind: ParamKind::Optional,
+                constraint,
+                ..
+            }] => Ok(Segment::Optional(constraint.clone())),
+            [PathPart::Param { constraint, .. }] => Ok(Segment::Param(constraint.clone())),
+            _ => {
+                let shape = parts
+                    .iter()
+                    .map(|part| match part {
+                        PathPart::Static(part) => part.clone(),
+                        PathPart::Param {
+                            constraint: Some(constraint),
+                            ..
+                        } => format!("{{:{}}}", constraint.regex()),
+                        PathPart::Param { .. } => "{}".to_string(),
+                    })
+                    .collect();
+                Ok(Segment::Dynamic(compile_regex(&parts)?, shape))
+            }
+        })
+        .collect()
+}
+
+// Returns all variants of the path, with and without each optional segment.
+fn expand_optional(segments: &[Segment]) -> Vec<Vec<Segment>> {
+    let mut variants: Vec<Vec<Segment>> = vec![Vec::new()];
+    for segment in segments {
+        match segment {
+            Segment::Optional(constraint) => {
+                let mut with_segment = variants.clone();
+                for variant in with_segment.iter_mut() {
+                    variant.push(Segment::Param(constraint.clone()));
                 }
+                variants.extend(with_segment);
             }
-            Some(caps) => {
-                let m = caps.get(0).unwrap();
-                let unmatched = &self.text[self.last..m.start()];
-                self.last = m.end();
-                self.caps = Some(caps);
-                Some(SplitState::Unmatched(unmatched))
+            segment => {
+                for variant in variants.iter_mut() {
+                    variant.push(segment.clone());
+                }
             }
         }
     }
+    variants
+}
+
+fn segment_regex(constraint: &Option<Constraint>) -> Regex {
+    let value_regex = value_regex(ParamKind::Single, constraint);
+    Regex::new(&format!("^{}$", value_regex)).unwrap()
+}
+
+fn compare_segments(earlier: &[Segment], later: &[Segment]) -> Relation {
+    let (left, right) = match (earlier.first(), later.first()) {
+        (None, None) | (Some(Segment::Rest), Some(Segment::Rest)) => return Relation::SAME,
+        // A wildcard matches the rest of the path, whatever it is
+        (Some(Segment::Rest), _) => return Relation::WIDER,
+        (_, Some(Segment::Rest)) => return Relation::NARROWER,
+        (None, Some(_)) | (Some(_), None) => return Relation::DISJOINT,
+        (Some(left), Some(right)) => (left, right),
+    };
+
+    let relation = match (left, right) {
+        (Segment::Literal(left), Segment::Literal(right)) if left == right => Relation::SAME,
+        (Segment::Literal(left), Segment::Dynamic(regex, _)) if regex.is_match(left) => {
+            Relation::NARROWER
+        }
+        (Segment::Dynamic(regex, _), Segment::Literal(right)) if regex.is_match(right) => {
+            Relation::WIDER
+        }
+        (Segment::Literal(left), Segment::Param(constraint))
+            if segment_regex(constraint).is_match(left) =>
+        {
+            Relation::NARROWER
+        }
+        (Segment::Param(constraint), Segment::Literal(right))
+            if segment_regex(constraint).is_match(right) =>
+        {
+            Relation::WIDER
+        }
+        (Segment::Param(left), Segment::Param(right)) => Constraint::relation(left, right),
+        // An unconstrained parameter takes any segment
+        (Segment::Dynamic(..), Segment::Param(None)) => Relation::NARROWER,
+        (Segment::Param(None), Segment::Dynamic(..)) => Relation::WIDER,
+        (Segment::Dynamic(..), Segment::Param(_)) | (Segment::Param(_), Segment::Dynamic(..)) => {
+            Relation::AMBIGUOUS
+        }
+        (Segment::Dynamic(_, left), Segment::Dynamic(_, right)) if left == right => Relation::SAME,
+        // Different dynamic segments can't be compared reliably, so consider them ambiguous
+        (Segment::Dynamic(..), Segment::Dynamic(..)) => Relation::AMBIGUOUS,
+        _ => Relation::DISJOINT,
+    };
+    if !relation.intersects {
+        return Relation::DISJOINT;
+    }
+
+    let rest = compare_segments(&earlier[1..], &later[1..]);
+    if !rest.intersects {
+        return Relation::DISJOINT;
+    }
+    Relation::new(
+        true,
+        relation.narrower && rest.narrower,
+        relation.wider && rest.wider,
+    )
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
-    use crate::routing::pattern::PatternType;
+    use crate::routing::pattern::{overlap, Constraint, Overlap, PatternType};
     use std::str::FromStr;
 
     #[test]
@@ -165,6 +684,14 @@ mod tests {
         assert_eq!(pattern_type.is_match(url_example), true);
     }
 
+    #[test]
+    fn test_dynamic_path_matches_whole_url() {
+        let pattern_type = PatternType::from_str("/users/{id}").unwrap();
+
+        assert!(!pattern_type.is_match("/api/users/1"));
+        assert!(!pattern_type.is_match("/users/1/detail"));
+    }
+
     #[test]
     fn test_parse_path_with_multiple_parameters() {
         let path = "/api/v1/blog/{blog_id}/users/{user_id}";
@@ -188,4 +715,129 @@ mod tests {
             "found an invalid \"}/\" part of the \"/api/v1/blog/{blog_id}}/\" path."
         );
     }
+
+    #[test]
+    fn test_get_invalid_path_error_for_unclosed_brace() {
+        let result = PatternType::from_str("/api/v1/blog/{blog_id/");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_wildcard_path() {
+        let pattern_type = PatternType::from_str("/static/{*path}").unwrap();
+
+        assert!(pattern_type.is_match("/static"));
+        let params = pattern_type.params("/static/css/main.css").unwrap();
+        assert_eq!(params, [("path".to_string(), "css/main.css".to_string())]);
+        assert!(!pattern_type.is_match("/staticfiles"));
+    }
+
+    #[test]
+    fn test_parse_optional_segment() {
+        let pattern_type = PatternType::from_str("/posts/{page?}/comments").unwrap();
+
+        assert_eq!(pattern_type.params("/posts/comments").unwrap(), []);
+        let params = pattern_type.params("/posts/2/comments").unwrap();
+        assert_eq!(params, [("page".to_string(), "2".to_string())]);
+        assert!(!pattern_type.is_match("/posts/2/3/comments"));
+    }
+
+    #[test]
+    fn test_get_invalid_path_error_for_misplaced_wildcard() {
+        assert!(PatternType::from_str("/static/{*path}/edit").is_err());
+        assert!(PatternType::from_str("/static/file{*path}").is_err());
+        assert!(PatternType::from_str("/posts/page-{number?}").is_err());
+    }
+
+    #[test]
+    fn test_parse_constrained_parameters() {
+        let pattern_type = PatternType::from_str("/users/{id:int}").unwrap();
+        assert!(pattern_type.is_match("/users/-42"));
+        assert!(!pattern_type.is_match("/users/abc"));
+        assert_eq!(pattern_type.constraints(), vec![("id", &Constraint::Int)]);
+
+        let pattern_type = PatternType::from_str("/posts/{slug:[a-z-]+}").unwrap();
+        assert!(pattern_type.is_match("/posts/hello-world"));
+        assert!(!pattern_type.is_match("/posts/Hello"));
+
+        let pattern_type = PatternType::from_str("/items/{uuid:uuid}").unwrap();
+        assert!(pattern_type.is_match("/items/67e55044-10b1-426f-9247-bb680e5fe0c8"));
+        assert!(!pattern_type.is_match("/items/67e55044"));
+
+        let pattern_type = PatternType::from_str("/years/{year:[0-9]{4}}").unwrap();
+        assert!(pattern_type.is_match("/years/2020"));
+        assert!(!pattern_type.is_match("/years/20"));
+    }
+
+    #[test]
+    fn test_get_error_for_invalid_constraint() {
+        let result = PatternType::from_str("/users/{id:integer}");
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "unknown \"integer\" constraint in the \"/users/{id:integer}\" path."
+        );
+        assert!(PatternType::from_str("/users/{id:[0-9}").is_err());
+    }
+
+    #[test]
+    fn test_build_path_from_params() {
+        let pattern_type = PatternType::from_str("/users/{id:int}/{tab?}").unwrap();
+        assert_eq!(
+            pattern_type.build_path("user", &[("id", "42")]).unwrap(),
+            "/users/42"
+        );
+        assert_eq!(
+            pattern_type
+                .build_path("user", &[("id", "42"), ("tab", "posts & likes")])
+                .unwrap(),
+            "/users/42/posts%20%26%20likes"
+        );
+
+        let pattern_type = PatternType::from_str("/static/{*path}").unwrap();
+        let path = pattern_type.build_path("static", &[("path", "css/main page.css")]);
+        assert_eq!(path.unwrap(), "/static/css/main%20page.css");
+    }
+
+    #[test]
+    fn test_get_error_for_missing_or_invalid_params() {
+        let pattern_type = PatternType::from_str("/users/{id:int}").unwrap();
+        assert!(pattern_type.build_path("user", &[]).is_err());
+        assert!(pattern_type.build_path("user", &[("id", "abc")]).is_err());
+    }
+
+    #[test]
+    fn test_overlap_between_paths() {
+        assert_eq!(overlap("/users/me", "/users/{id}"), Overlap::Narrower);
+        assert_eq!(overlap("/users/{id}", "/users/me"), Overlap::Wider);
+        assert_eq!(overlap("/users/{id}", "/users/{name}"), Overlap::Same);
+        assert_eq!(overlap("/a/{x}/b", "/a/c/{y}"), Overlap::Ambiguous);
+        assert_eq!(
+            overlap("/users/{id}", "/users/{id}/posts"),
+            Overlap::Disjoint
+        );
+        assert_eq!(overlap("/users", "/posts"), Overlap::Disjoint);
+        assert_eq!(
+            overlap("/static/app.js", "/static/{*path}"),
+            Overlap::Narrower
+        );
+        assert_eq!(overlap("/{*path}", "/users/{id}"), Overlap::Wider);
+        assert_eq!(overlap("/posts", "/posts/{page?}"), Overlap::Narrower);
+        assert_eq!(overlap("/posts/{page?}", "/posts/{number}"), Overlap::Wider);
+        assert_eq!(
+            overlap("/users/{id:int}", "/users/{name}"),
+            Overlap::Narrower
+        );
+        assert_eq!(
+            overlap("/users/{id:int}", "/users/{name:alpha}"),
+            Overlap::Disjoint
+        );
+        assert_eq!(
+            overlap("/users/{id:float}", "/users/{id:uint}"),
+            Overlap::Wider
+        );
+        assert_eq!(
+            overlap("/users/{id:[0-9]+}", "/users/{name:int}"),
+            Overlap::Ambiguous
+        );
+    }
 }
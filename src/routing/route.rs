@@ -0,0 +1,81 @@
+use crate::error::RoutingError;
+use crate::handler::Handler;
+use crate::middleware::Middleware;
+use crate::routing::endpoint::{into_handler, BoxHandler, Endpoint, MethodType};
+use crate::routing::guard::Guard;
+use crate::routing::pattern::PatternType;
+use std::{result, str::FromStr, sync::Arc};
+
+// Declaration of an endpoint with its optional settings, turned into an
+// endpoint when added to the router.
+pub struct Route {
+    path: String,
+    methods: Vec<String>,
+    handler: BoxHandler,
+    name: Option<String>,
+    guards: Vec<Arc<dyn Guard>>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl Route {
+    pub fn new<H, Args>(path: &str, methods: &[&str], exec: H) -> Self
+    where
+        H: Handler<Args>,
+    {
+        Route::from_handler(path, methods, into_handler(exec))
+    }
+
+    pub(crate) fn from_handler(path: &str, methods: &[&str], handler: BoxHandler) -> Self {
+        Route {
+            path: path.to_string(),
+            methods: methods.iter().map(|method| method.to_string()).collect(),
+            handler,
+            name: None,
+            guards: Vec::new(),
+            middlewares: Vec::new(),
+        }
+    }
+
+    // Names the route, so its URL can be built with `Router::url_for`.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    // Adds a condition the request must satisfy to be routed to the endpoint
+    // (e.g. a `Host` or a `Header` guard). Guards are checked in the order of declaration.
+    pub fn guard<G: Guard>(mut self, guard: G) -> Self {
+        self.guards.push(Arc::new(guard));
+        self
+    }
+
+    // Adds a middleware running only for the requests routed to the endpoint.
+    pub fn middleware<M: Middleware>(mut self, middleware: M) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    pub(crate) fn into_endpoint(self) -> result::Result<Endpoint, RoutingError> {
+        let methods = parse_methods(&self.path, &self.methods)?;
+        let pattern = PatternType::parse(&self.path)?;
+        let mut endpoint = Endpoint::with_pattern(&self.path, pattern, methods, self.handler);
+        endpoint.set_name(self.name);
+        endpoint.set_guards(self.guards);
+        endpoint.set_middlewares(self.middlewares);
+        Ok(endpoint)
+    }
+}
+
+// Converts the method names into method types, failing on the first invalid one.
+fn parse_methods(path: &str, methods: &[String]) -> result::Result<Vec<MethodType>, RoutingError> {
+    methods
+        .iter()
+        .map(|method| {
+            let fixed_method_name = method.trim().to_uppercase();
+            MethodType::from_str(&fixed_method_name).map_err(|_| RoutingError::InvalidMethodError {
+                method: method.to_string(),
+                path: path.to_string(),
+            })
+        })
+        .collect()
+}
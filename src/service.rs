@@ -0,0 +1,429 @@
+use crate::error::ServiceError;
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, IntoResponse};
+use crate::router::Router;
+use crate::routing::endpoint::BoxHandler;
+use crate::routing::route::Route;
+use crate::status_code::StatusCode;
+use futures::future::{self, poll_fn, BoxFuture, Either, FutureExt};
+use futures_timer::Delay;
+use std::convert::Infallible;
+use std::error::Error;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{Layer, Service};
+use tracing::error;
+
+// Bridges between alcazar and tower services. The futures of the services are
+// polled by the bastion executor running the handlers, not by tokio: the tower
+// middleware using tokio timers (`tower::timeout`, `tower::limit::RateLimit` or
+// the retry backoffs) need a tokio runtime running alongside the application, or
+// they never wake up. `TimeoutLayer` is a timeout working without it.
+//
+// Retry policies clone the requests with `HttpRequest::try_clone`, which needs
+// their body to be buffered first with `Body::buffer`.
+
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+impl Service<HttpRequest> for Router {
+    type Response = HttpResponse;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        let router = self.clone();
+        async move { Ok(router.dispatch(request).await) }.boxed()
+    }
+}
+
+impl Router {
+    // Adds an endpoint handled by the tower service.
+    pub fn with_service<S>(self, path: &str, methods: &[&str], service: S) -> Self
+    where
+        S: Service<HttpRequest> + Clone + Send + Sync + 'static,
+        S::Response: IntoResponse,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+    {
+        self.with_route(Route::service(path, methods, service))
+    }
+
+    // Adds the tower layer as a middleware of the router. The layer wraps the rest
+    // of the pipeline once, and the resulting service is cloned for every request,
+    // so the layers sharing state between the clones (e.g. concurrency limits)
+    // apply to all the requests.
+    pub fn with_layer<L>(self, layer: L) -> Self
+    where
+        L: Layer<NextService>,
+        LayerMiddleware<L::Service>: Middleware,
+    {
+        self.with_middleware(LayerMiddleware::new(layer))
+    }
+}
+
+impl Route {
+    // Declares an endpoint handled by the tower service.
+    pub fn service<S>(path: &str, methods: &[&str], service: S) -> Self
+    where
+        S: Service<HttpRequest> + Clone + Send + Sync + 'static,
+        S::Response: IntoResponse,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+    {
+        let handler: BoxHandler = Arc::new(move |request| {
+            let service = service.clone();
+            async move { into_response(call_service(service, request).await) }.boxed()
+        });
+        Route::from_handler(path, methods, handler)
+    }
+}
+
+// The rest of the middleware pipeline, exposed as a service wrapped by tower layers.
+// The pipeline is the one of the request, which carries it through the layers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NextService;
+
+impl Service<HttpRequest> for NextService {
+    type Response = HttpResponse;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<HttpResponse, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut request: HttpRequest) -> Self::Future {
+        match request.take_next() {
+            Some(next) => next.run(request).map(Ok).boxed(),
+            None => {
+                error!("The request reached the end of a tower layer without its pipeline.");
+                future::ready(Ok(HttpResponse::new(StatusCode::InternalServerError))).boxed()
+            }
+        }
+    }
+}
+
+// Runs a tower layer as a middleware, wrapping the rest of the pipeline.
+pub struct LayerMiddleware<S> {
+    service: Mutex<S>,
+}
+
+impl<S> LayerMiddleware<S> {
+    pub fn new<L: Layer<NextService, Service = S>>(layer: L) -> Self {
+        LayerMiddleware {
+            service: Mutex::new(layer.layer(NextService)),
+        }
+    }
+}
+
+impl<S> Middleware for LayerMiddleware<S>
+where
+    S: Service<HttpRequest> + Clone + Send + 'static,
+    S::Response: IntoResponse,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    fn handle<'a>(&'a self, mut request: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse> {
+        let service = self
+            .service
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        request.set_next(next);
+        async move { into_response(call_service(service, request).await) }.boxed()
+    }
+}
+
+// Fails the requests taking longer than the timeout, which are answered with 408
+// Request Timeout. Unlike `tower::timeout`, it doesn't need a tokio runtime. The
+// wrapped futures have to yield to be interrupted, which the blocking reads of the
+// request body don't.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+}
+
+impl TimeoutLayer {
+    pub fn new(timeout: Duration) -> Self {
+        TimeoutLayer { timeout }
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = Timeout<S>;
+
+    fn layer(&self, inner: S) -> Timeout<S> {
+        Timeout {
+            inner,
+            timeout: self.timeout,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Timeout<S> {
+    inner: S,
+    timeout: Duration,
+}
+
+impl<S, R> Service<R> for Timeout<S>
+where
+    S: Service<R>,
+    S::Response: 'static,
+    S::Error: Into<BoxError> + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<S::Response, BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let response = self.inner.call(request).boxed();
+        let timeout = self.timeout;
+        async move {
+            match future::select(response, Delay::new(timeout)).await {
+                Either::Left((result, _)) => result.map_err(Into::into),
+                Either::Right(_) => Err(ServiceError::Timeout(timeout).into()),
+            }
+        }
+        .boxed()
+    }
+}
+
+async fn call_service<S>(mut service: S, request: HttpRequest) -> Result<S::Response, S::Error>
+where
+    S: Service<HttpRequest>,
+{
+    poll_fn(|cx| service.poll_ready(cx)).await?;
+    service.call(request).await
+}
+
+// Failed services are answered with an internal server error, or 408 Request
+// Timeout when they timed out.
+fn into_response<R, E>(result: Result<R, E>) -> HttpResponse
+where
+    R: IntoResponse,
+    E: Into<BoxError>,
+{
+    match result {
+        Ok(response) => response.into_response(),
+        Err(err) => {
+            let err = err.into();
+            if let Some(ServiceError::Timeout(_)) = err.downcast_ref() {
+                return HttpResponse::new(StatusCode::RequestTimeout);
+            }
+            error!("The service failed: {}", err);
+            HttpResponse::new(StatusCode::InternalServerError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::service::{call_service, BoxError, TimeoutLayer};
+    use crate::status_code::StatusCode;
+    use bastion_executor::run::run;
+    use futures::future::{ready, BoxFuture, Ready};
+    use futures_timer::Delay;
+    use lightproc::prelude::ProcStack;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+    use tower::layer::layer_fn;
+    use tower::limit::ConcurrencyLimitLayer;
+    use tower::retry::{Policy, RetryLayer};
+    use tower::Service;
+
+    // Answers with the request's path, or fails for `/error`
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<HttpRequest> for Echo {
+        type Response = String;
+        type Error = BoxError;
+        type Future = Ready<Result<String, BoxError>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: HttpRequest) -> Self::Future {
+            match request.path() {
+                "/error" => ready(Err("echo failed".into())),
+                path => ready(Ok(path.to_string())),
+            }
+        }
+    }
+
+    // Sets the `X-Layer` header of the wrapped service's responses
+    #[derive(Clone)]
+    struct Tagged<S> {
+        inner: S,
+    }
+
+    impl<S> Service<HttpRequest> for Tagged<S>
+    where
+        S: Service<HttpRequest, Response = HttpResponse> + Send + 'static,
+        S::Future: Send + 'static,
+    {
+        type Response = HttpResponse;
+        type Error = S::Error;
+        type Future = BoxFuture<'static, Result<HttpResponse, S::Error>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, request: HttpRequest) -> Self::Future {
+            let future = self.inner.call(request);
+            Box::pin(async move {
+                let response = future.await?;
+                Ok(response.with_header("X-Layer", "tagged"))
+            })
+        }
+    }
+
+    #[test]
+    fn test_mount_service_as_endpoint() {
+        let router = Router::new().with_service("/{*path}", &["get"], Echo);
+
+        let request = HttpRequest::new(MethodType::GET, "/echo");
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.body(), b"/echo");
+
+        let request = HttpRequest::new(MethodType::GET, "/error");
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.status(), StatusCode::InternalServerError);
+    }
+
+    #[test]
+    fn test_use_router_as_service_with_layer() {
+        let router = Router::new()
+            .with_layer(layer_fn(|inner| Tagged { inner }))
+            .with_service("/echo", &["get"], Echo);
+
+        let request = HttpRequest::new(MethodType::GET, "/echo");
+        let response = run(call_service(router, request), ProcStack::default()).unwrap();
+        assert_eq!(response.headers().get("X-Layer"), Some("tagged"));
+        assert_eq!(response.body(), b"/echo");
+    }
+
+    static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+    static MAX_ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+    async fn slow() -> StatusCode {
+        let active = ACTIVE.fetch_add(1, Ordering::SeqCst) + 1;
+        MAX_ACTIVE.fetch_max(active, Ordering::SeqCst);
+        Delay::new(Duration::from_millis(100)).await;
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+        StatusCode::Ok
+    }
+
+    #[test]
+    fn test_layer_state_is_shared_between_requests() {
+        let router = Router::new()
+            .with_layer(ConcurrencyLimitLayer::new(1))
+            .with_endpoint("/slow", &["get"], slow);
+
+        let requests: Vec<_> = (0..2)
+            .map(|_| {
+                let router = router.clone();
+                thread::spawn(move || {
+                    let request = HttpRequest::new(MethodType::GET, "/slow");
+                    run(router.dispatch(request), ProcStack::default())
+                })
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(request.join().unwrap().status(), StatusCode::Ok);
+        }
+        assert_eq!(MAX_ACTIVE.load(Ordering::SeqCst), 1);
+    }
+
+    // Retries the requests answered with 503 Service Unavailable
+    #[derive(Clone)]
+    struct RetryUnavailable {
+        attempts: usize,
+    }
+
+    impl<E> Policy<HttpRequest, HttpResponse, E> for RetryUnavailable {
+        type Future = Ready<()>;
+
+        fn retry(
+            &mut self,
+            _request: &mut HttpRequest,
+            result: &mut Result<HttpResponse, E>,
+        ) -> Option<Ready<()>> {
+            match result {
+                Ok(response) if response.status() == StatusCode::ServiceUnavailable => {
+                    self.attempts = self.attempts.checked_sub(1)?;
+                    Some(ready(()))
+                }
+                _ => None,
+            }
+        }
+
+        fn clone_request(&mut self, request: &HttpRequest) -> Option<HttpRequest> {
+            request.try_clone()
+        }
+    }
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    // Fails the first two calls
+    async fn flaky(body: String) -> (StatusCode, String) {
+        match CALLS.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => (StatusCode::ServiceUnavailable, String::new()),
+            _ => (StatusCode::Ok, body),
+        }
+    }
+
+    #[test]
+    fn test_retry_layer() {
+        let router = Router::new()
+            .with_layer(RetryLayer::new(RetryUnavailable { attempts: 2 }))
+            .with_endpoint("/flaky", &["post"], flaky);
+
+        let request = HttpRequest::new(MethodType::POST, "/flaky").with_body("payload");
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.body(), b"payload");
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+    }
+
+    async fn sleepy() -> StatusCode {
+        Delay::new(Duration::from_secs(5)).await;
+        StatusCode::Ok
+    }
+
+    #[test]
+    fn test_timeout_layer() {
+        let router = Router::new()
+            .with_layer(TimeoutLayer::new(Duration::from_millis(50)))
+            .with_endpoint("/sleepy", &["get"], sleepy)
+            .with_service("/echo", &["get"], Echo);
+
+        let request = HttpRequest::new(MethodType::GET, "/sleepy");
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.status(), StatusCode::RequestTimeout);
+
+        let request = HttpRequest::new(MethodType::GET, "/echo");
+        let response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(response.body(), b"/echo");
+    }
+}
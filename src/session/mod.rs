@@ -0,0 +1,408 @@
+use crate::extract::cookie::{parse_jar, time, Cookie, SameSite};
+use crate::extract::{FromRequest, Rejection};
+use crate::middleware::{Middleware, Next};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::status_code::StatusCode;
+use futures::future::{ready, BoxFuture, FutureExt};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime};
+use tracing::error;
+
+mod store;
+
+pub use store::{FileStore, MemoryStore, SessionRecord, SessionStore};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Unchanged,
+    Changed,
+    // Saved under a new id, see `Session::renew`
+    Renewed,
+    Purged,
+}
+
+#[derive(Debug)]
+struct SessionState {
+    id: Option<String>,
+    data: HashMap<String, Value>,
+    status: Status,
+    ttl: Option<Duration>,
+}
+
+// Session of the request, loaded by the `SessionMiddleware` and saved once the
+// response is ready if it was modified. Values are stored as JSON, so they can be
+// read back as any compatible type.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+    fn new(id: Option<String>, data: HashMap<String, Value>) -> Self {
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                id,
+                data,
+                status: Status::Unchanged,
+                ttl: None,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn change(state: &mut SessionState) {
+        if state.status == Status::Unchanged {
+            state.status = Status::Changed;
+        }
+    }
+
+    // Returns the id of the session, which is `None` until a new session is saved.
+    pub fn id(&self) -> Option<String> {
+        self.lock().id.clone()
+    }
+
+    // Returns the value of the key, or `None` if it's missing or of another type.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.lock().data.get(key).cloned()?;
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn insert<T: Serialize>(&self, key: &str, value: T) -> Result<(), serde_json::Error> {
+        let value = serde_json::to_value(value)?;
+        let mut state = self.lock();
+        state.data.insert(key.to_string(), value);
+        Session::change(&mut state);
+        Ok(())
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut state = self.lock();
+        if state.data.remove(key).is_some() {
+            Session::change(&mut state);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        Session::change(&mut state);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().data.is_empty()
+    }
+
+    // Moves the session to a new id, to be called when the privileges of the user
+    // change (e.g. on login), so a session id known before can't be used anymore.
+    pub fn renew(&self) {
+        let mut state = self.lock();
+        if state.status != Status::Purged {
+            state.status = Status::Renewed;
+        }
+    }
+
+    // Destroys the session and removes its cookie (e.g. on logout).
+    pub fn purge(&self) {
+        let mut state = self.lock();
+        state.data.clear();
+        state.status = Status::Purged;
+    }
+
+    // Overrides the lifetime of the session given by the middleware.
+    pub fn set_expiry(&self, ttl: Duration) {
+        let mut state = self.lock();
+        state.ttl = Some(ttl);
+        Session::change(&mut state);
+    }
+}
+
+// Session of the request, rejected with 500 Internal Server Error when the
+// `SessionMiddleware` isn't running for the route.
+impl FromRequest for Session {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let result = request
+            .extensions()
+            .get::<Session>()
+            .cloned()
+            .ok_or_else(|| {
+                Rejection::new(
+                    StatusCode::InternalServerError,
+                    "the session middleware isn't running for the route",
+                )
+            });
+        ready(result).boxed()
+    }
+}
+
+// Returns a new session id of 256 random bits.
+fn generate_id() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Loads the session identified by the cookie of the request, and saves it with the
+// store once the response is ready. New sessions are only saved once modified,
+// and sessions expire after their lifetime (a day by default) without any change.
+pub struct SessionMiddleware<S> {
+    store: Arc<S>,
+    cookie_name: String,
+    cookie_path: String,
+    cookie_domain: Option<String>,
+    secure: bool,
+    same_site: SameSite,
+    ttl: Duration,
+}
+
+impl<S: SessionStore> SessionMiddleware<S> {
+    pub fn new(store: S) -> Self {
+        SessionMiddleware {
+            store: Arc::new(store),
+            cookie_name: "alcazar.sid".to_string(),
+            cookie_path: "/".to_string(),
+            cookie_domain: None,
+            secure: true,
+            same_site: SameSite::Lax,
+            ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    pub fn cookie_path(mut self, path: &str) -> Self {
+        self.cookie_path = path.to_string();
+        self
+    }
+
+    pub fn cookie_domain(mut self, domain: &str) -> Self {
+        self.cookie_domain = Some(domain.to_string());
+        self
+    }
+
+    // Sets the `Secure` attribute of the cookie, enabled by default.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn cookie(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build((self.cookie_name.clone(), value))
+            .path(self.cookie_path.clone())
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .build();
+        if let Some(domain) = &self.cookie_domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+
+    async fn load(&self, id: Option<String>) -> Session {
+        let id = match id {
+            Some(id) => id,
+            None => return Session::new(None, HashMap::new()),
+        };
+        match self.store.load(&id).await {
+            Ok(Some(record)) if !record.is_expired() => Session::new(Some(id), record.data),
+            Ok(_) => Session::new(None, HashMap::new()),
+            Err(err) => {
+                error!("failed to load the session: {}", err);
+                Session::new(None, HashMap::new())
+            }
+        }
+    }
+
+    async fn save(&self, session: &Session, had_cookie: bool, response: &mut HttpResponse) {
+        let (status, id, data, ttl) = {
+            let state = session.lock();
+            let ttl = state.ttl.unwrap_or(self.ttl);
+            (state.status, state.id.clone(), state.data.clone(), ttl)
+        };
+
+        let result = match status {
+            Status::Unchanged => Ok(()),
+            Status::Purged => {
+                if had_cookie {
+                    response.remove_cookie(self.cookie(String::new()));
+                }
+                match id {
+                    Some(id) => self.store.remove(&id).await,
+                    None => Ok(()),
+                }
+            }
+            Status::Changed | Status::Renewed => {
+                let previous_id = match status {
+                    Status::Renewed => id.clone(),
+                    _ => None,
+                };
+                let id = match id {
+                    Some(id) if status == Status::Changed => id,
+                    _ => generate_id(),
+                };
+                let record = SessionRecord {
+                    data,
+                    expires_at: SystemTime::now() + ttl,
+                };
+                let mut cookie = self.cookie(id.clone());
+                cookie.set_max_age(time::Duration::seconds(ttl.as_secs() as i64));
+                response.add_cookie(cookie);
+
+                let mut result = self.store.save(&id, record).await;
+                if let (Ok(()), Some(previous_id)) = (&result, previous_id) {
+                    result = self.store.remove(&previous_id).await;
+                }
+                result
+            }
+        };
+
+        if let Err(err) = result {
+            error!("failed to save the session: {}", err);
+            *response = HttpResponse::new(StatusCode::InternalServerError);
+        }
+    }
+}
+
+impl<S: SessionStore> Middleware for SessionMiddleware<S> {
+    fn handle<'a>(&'a self, mut request: HttpRequest, next: Next) -> BoxFuture<'a, HttpResponse> {
+        async move {
+            let id = parse_jar(&request)
+                .get(&self.cookie_name)
+                .map(|cookie| cookie.value().to_string());
+            let had_cookie = id.is_some();
+            let session = self.load(id).await;
+            request.extensions_mut().insert(session.clone());
+
+            let mut response = next.run(request).await;
+            self.save(&session, had_cookie, &mut response).await;
+            response
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::request::HttpRequest;
+    use crate::response::HttpResponse;
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::session::{MemoryStore, Session, SessionMiddleware};
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    async fn login(session: Session) -> &'static str {
+        session.insert("user", "alice").unwrap();
+        session.renew();
+        "logged in"
+    }
+
+    async fn whoami(session: Session) -> String {
+        let visits: u32 = session.get("visits").unwrap_or(0);
+        session.insert("visits", visits + 1).unwrap();
+        format!("{:?} {}", session.get::<String>("user"), visits)
+    }
+
+    async fn logout(session: Session) -> &'static str {
+        session.purge();
+        "logged out"
+    }
+
+    fn router(store: Arc<MemoryStore>, ttl: Duration) -> Router {
+        Router::new()
+            .with_middleware(SessionMiddleware::new(store).ttl(ttl))
+            .with_endpoint("/login", &["post"], login)
+            .with_endpoint("/whoami", &["get"], whoami)
+            .with_endpoint("/logout", &["post"], logout)
+    }
+
+    fn dispatch(
+        router: &Router,
+        method: MethodType,
+        path: &str,
+        cookie: Option<&str>,
+    ) -> HttpResponse {
+        let mut request = HttpRequest::new(method, path);
+        if let Some(cookie) = cookie {
+            request = request.with_header("Cookie", cookie);
+        }
+        run(router.dispatch(request), ProcStack::default())
+    }
+
+    // Returns the `name=value` part of the session cookie
+    fn session_cookie(response: &HttpResponse) -> Option<String> {
+        let cookie = response.headers().get("Set-Cookie")?;
+        Some(cookie.split(';').next().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_session_lifecycle() {
+        let store = Arc::new(MemoryStore::new());
+        let router = router(store.clone(), Duration::from_secs(60));
+
+        let response = dispatch(&router, MethodType::GET, "/whoami", None);
+        assert_eq!(response.body(), b"None 0");
+        let anonymous = session_cookie(&response).unwrap();
+        assert!(response
+            .headers()
+            .get("Set-Cookie")
+            .unwrap()
+            .contains("HttpOnly"));
+
+        // Logging in moves the session to a new id
+        let response = dispatch(&router, MethodType::POST, "/login", Some(&anonymous));
+        let logged_in = session_cookie(&response).unwrap();
+        assert_ne!(logged_in, anonymous);
+        assert_eq!(store.len(), 1);
+
+        let response = dispatch(&router, MethodType::GET, "/whoami", Some(&anonymous));
+        assert_eq!(response.body(), b"None 0");
+        let response = dispatch(&router, MethodType::GET, "/whoami", Some(&logged_in));
+        assert_eq!(response.body(), b"Some(\"alice\") 1");
+
+        let response = dispatch(&router, MethodType::POST, "/logout", Some(&logged_in));
+        assert!(response
+            .headers()
+            .get("Set-Cookie")
+            .unwrap()
+            .contains("Max-Age=0"));
+        let response = dispatch(&router, MethodType::GET, "/whoami", Some(&logged_in));
+        assert_eq!(response.body(), b"None 0");
+    }
+
+    #[test]
+    fn test_session_expires() {
+        let store = Arc::new(MemoryStore::new());
+        let router = router(store, Duration::from_secs(0));
+
+        let response = dispatch(&router, MethodType::POST, "/login", None);
+        let cookie = session_cookie(&response).unwrap();
+        let response = dispatch(&router, MethodType::GET, "/whoami", Some(&cookie));
+        assert_eq!(response.body(), b"None 0");
+    }
+}
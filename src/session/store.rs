@@ -0,0 +1,255 @@
+use futures::future::{ready, BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+
+// Saved content of a session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub data: HashMap<String, Value>,
+    pub expires_at: SystemTime,
+}
+
+impl SessionRecord {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= SystemTime::now()
+    }
+}
+
+// Storage of the sessions by id. Stores must not return expired sessions.
+pub trait SessionStore: Send + Sync + 'static {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>>;
+
+    fn save<'a>(&'a self, id: &'a str, record: SessionRecord) -> BoxFuture<'a, io::Result<()>>;
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+// Lets the application keep a handle to the store given to the middleware.
+impl<S: SessionStore> SessionStore for Arc<S> {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>> {
+        (**self).load(id)
+    }
+
+    fn save<'a>(&'a self, id: &'a str, record: SessionRecord) -> BoxFuture<'a, io::Result<()>> {
+        (**self).save(id, record)
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        (**self).remove(id)
+    }
+}
+
+// Keeps the sessions in memory, so they are lost when the application stops.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    // Drops the expired sessions.
+    pub fn cleanup(&self) {
+        self.lock().retain(|_, record| !record.is_expired());
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, SessionRecord>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>> {
+        let mut sessions = self.lock();
+        let record = match sessions.get(id) {
+            Some(record) if record.is_expired() => {
+                sessions.remove(id);
+                None
+            }
+            record => record.cloned(),
+        };
+        ready(Ok(record)).boxed()
+    }
+
+    fn save<'a>(&'a self, id: &'a str, record: SessionRecord) -> BoxFuture<'a, io::Result<()>> {
+        self.lock().insert(id.to_string(), record);
+        ready(Ok(())).boxed()
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        self.lock().remove(id);
+        ready(Ok(())).boxed()
+    }
+}
+
+// Keeps every session as a JSON file of the directory, named after its id.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    // Returns a store in the directory, created if needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+
+    // Deletes the files of the expired sessions.
+    pub fn cleanup(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                let expired = fs::read(&path)
+                    .ok()
+                    .and_then(|content| serde_json::from_slice::<SessionRecord>(&content).ok())
+                    .is_none_or(|record| record.is_expired());
+                if expired {
+                    fs::remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the path of the session file, rejecting the ids which could escape
+    // the directory.
+    fn path(&self, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid session id {:?}", id),
+            ));
+        }
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+
+    fn load_file(&self, id: &str) -> io::Result<Option<SessionRecord>> {
+        let path = self.path(id)?;
+        let content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let record: SessionRecord = serde_json::from_slice(&content)?;
+        if record.is_expired() {
+            fs::remove_file(&path)?;
+            return Ok(None);
+        }
+        Ok(Some(record))
+    }
+
+    fn save_file(&self, id: &str, record: &SessionRecord) -> io::Result<()> {
+        let path = self.path(id)?;
+        // Write to a temporary file first, so the session is never read half-written
+        let mut file = NamedTempFile::new_in(&self.dir)?;
+        serde_json::to_writer(&mut file, record)?;
+        file.flush()?;
+        file.persist(path).map_err(|err| err.error)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)?) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<Option<SessionRecord>>> {
+        ready(self.load_file(id)).boxed()
+    }
+
+    fn save<'a>(&'a self, id: &'a str, record: SessionRecord) -> BoxFuture<'a, io::Result<()>> {
+        ready(self.save_file(id, &record)).boxed()
+    }
+
+    fn remove<'a>(&'a self, id: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        ready(self.remove_file(id)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::store::{FileStore, MemoryStore, SessionRecord, SessionStore};
+    use bastion_executor::run::run;
+    use lightproc::prelude::ProcStack;
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    fn record(ttl: Duration) -> SessionRecord {
+        let mut data = HashMap::new();
+        data.insert("user".to_string(), serde_json::json!("alice"));
+        SessionRecord {
+            data,
+            expires_at: SystemTime::now() + ttl,
+        }
+    }
+
+    fn check_store<S: SessionStore>(store: &S) {
+        run(
+            async {
+                store
+                    .save("abc", record(Duration::from_secs(60)))
+                    .await
+                    .unwrap();
+                let loaded = store.load("abc").await.unwrap().unwrap();
+                assert_eq!(loaded.data["user"], "alice");
+
+                store
+                    .save("old", record(Duration::from_secs(0)))
+                    .await
+                    .unwrap();
+                assert!(store.load("old").await.unwrap().is_none());
+
+                store.remove("abc").await.unwrap();
+                assert!(store.load("abc").await.unwrap().is_none());
+            },
+            ProcStack::default(),
+        );
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryStore::new();
+        check_store(&store);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileStore::new(dir.path().join("sessions")).unwrap();
+        check_store(&store);
+
+        let result = run(store.load("../secret"), ProcStack::default());
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::response::{HttpResponse, IntoResponse};
+use crate::status_code::StatusCode;
+use futures::stream::{Stream, StreamExt};
+use futures_timer::Delay;
+use std::error::Error;
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+// Event sent to the client of a Server-Sent Events stream.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+    comment: Option<String>,
+}
+
+// Line breaks would end the field early, so they are dropped from the single line fields.
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+impl Event {
+    pub fn new() -> Self {
+        Event::default()
+    }
+
+    // Sets the data, sent as one `data` field per line.
+    pub fn data(mut self, data: &str) -> Self {
+        self.data = Some(data.to_string());
+        self
+    }
+
+    #[cfg(feature = "json")]
+    pub fn json_data<T: serde::Serialize>(self, data: &T) -> Result<Self, serde_json::Error> {
+        Ok(self.data(&serde_json::to_string(data)?))
+    }
+
+    // Sets the id, sent back by the client in the `Last-Event-ID` header when reconnecting.
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    // Sets the name of the event, `message` when omitted.
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(single_line(event));
+        self
+    }
+
+    // Sets the delay the client waits for before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    // Adds a comment, ignored by the client.
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comment = Some(single_line(comment));
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut event = String::new();
+        if let Some(comment) = &self.comment {
+            let _ = writeln!(event, ": {}", comment);
+        }
+        if let Some(name) = &self.event {
+            let _ = writeln!(event, "event: {}", name);
+        }
+        if let Some(id) = &self.id {
+            let _ = writeln!(event, "id: {}", id);
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(event, "retry: {}", retry.as_millis());
+        }
+        if let Some(data) = &self.data {
+            for line in data.split('\n') {
+                let _ = writeln!(event, "data: {}", line.strip_suffix('\r').unwrap_or(line));
+            }
+        }
+        event.push('\n');
+        event.into_bytes()
+    }
+}
+
+// Server-Sent Events response, streaming the events as they come. While no event
+// is ready, a comment is sent every 15 seconds by default, so the proxies don't
+// close the idle connection. The id of the last event received by a reconnecting
+// client is given by the `Last-Event-ID` header (e.g. `Option<Header<LastEventId>>`).
+pub struct Sse<S> {
+    stream: S,
+    keep_alive: Option<Duration>,
+}
+
+impl<S, E> Sse<S>
+where
+    S: Stream<Item = Result<Event, E>> + Send + 'static,
+    E: Into<Box<dyn Error + Send + Sync>> + 'static,
+{
+    pub fn new(stream: S) -> Self {
+        Sse {
+            stream,
+            keep_alive: Some(Duration::from_secs(15)),
+        }
+    }
+
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    pub fn without_keep_alive(mut self) -> Self {
+        self.keep_alive = None;
+        self
+    }
+}
+
+impl<S, E> IntoResponse for Sse<S>
+where
+    S: Stream<Item = Result<Event, E>> + Send + 'static,
+    E: Into<Box<dyn Error + Send + Sync>> + 'static,
+{
+    fn into_response(self) -> HttpResponse {
+        let events = self.stream.map(|event| event.map(|event| event.to_bytes()));
+        let response = HttpResponse::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+            // Asks the proxies not to buffer the events
+            .with_header("X-Accel-Buffering", "no");
+        match self.keep_alive {
+            Some(interval) => response.with_stream(KeepAlive::new(events.boxed(), interval)),
+            None => response.with_stream(events),
+        }
+    }
+}
+
+// Sends a comment when the stream has been idle during the interval.
+struct KeepAlive<S> {
+    stream: S,
+    interval: Duration,
+    delay: Delay,
+}
+
+impl<S> KeepAlive<S> {
+    fn new(stream: S, interval: Duration) -> Self {
+        KeepAlive {
+            stream,
+            interval,
+            delay: Delay::new(interval),
+        }
+    }
+}
+
+impl<S, E> Stream for KeepAlive<S>
+where
+    S: Stream<Item = Result<Vec<u8>, E>> + Unpin,
+{
+    type Item = Result<Vec<u8>, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Poll::Ready(item) = this.stream.poll_next_unpin(cx) {
+            this.delay.reset(this.interval);
+            return Poll::Ready(item);
+        }
+        match Pin::new(&mut this.delay).poll(cx) {
+            Poll::Ready(()) => {
+                this.delay.reset(this.interval);
+                Poll::Ready(Some(Ok(b":\n\n".to_vec())))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extract::header::{Header, LastEventId};
+    use crate::request::HttpRequest;
+    use crate::response::{IntoResponse, ResponseBody};
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::sse::{Event, Sse};
+    use bastion_executor::run::run;
+    use futures::stream::{self, StreamExt};
+    use lightproc::prelude::ProcStack;
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    async fn updates(last_event_id: Option<Header<LastEventId>>) -> impl IntoResponse {
+        let start: u32 = last_event_id
+            .and_then(|Header(LastEventId(id))| id.parse().ok())
+            .unwrap_or(0);
+        let events = (start + 1..start + 3).map(|id| {
+            let event = Event::new()
+                .id(&id.to_string())
+                .event("update")
+                .data("line 1\nline 2");
+            Ok::<_, Infallible>(event)
+        });
+        Sse::new(stream::iter(events))
+    }
+
+    fn collect_chunks(body: ResponseBody, count: usize) -> Vec<String> {
+        let stream = match body {
+            ResponseBody::Stream(stream) => stream,
+            ResponseBody::Bytes(_) => panic!("expected a stream"),
+        };
+        let chunks = run(stream.take(count).collect::<Vec<_>>(), ProcStack::default());
+        chunks
+            .into_iter()
+            .map(|chunk| String::from_utf8(chunk.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_serialize_event() {
+        let event = Event::new()
+            .comment("hello")
+            .event("up\ndate")
+            .retry(Duration::from_secs(3))
+            .data("{}\r\n[]");
+        assert_eq!(
+            String::from_utf8(event.to_bytes()).unwrap(),
+            ": hello\nevent: update\nretry: 3000\ndata: {}\ndata: []\n\n"
+        );
+    }
+
+    #[test]
+    fn test_stream_events_from_last_event_id() {
+        let router = Router::new().with_endpoint("/updates", &["get"], updates);
+        let request =
+            HttpRequest::new(MethodType::GET, "/updates").with_header("Last-Event-ID", "7");
+
+        let mut response = run(router.dispatch(request), ProcStack::default());
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/event-stream")
+        );
+        assert_eq!(response.headers().get("Cache-Control"), Some("no-cache"));
+        assert_eq!(
+            collect_chunks(response.take_body(), 3),
+            [
+                "event: update\nid: 8\ndata: line 1\ndata: line 2\n\n",
+                "event: update\nid: 9\ndata: line 1\ndata: line 2\n\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_send_keep_alive_comments() {
+        let events = stream::pending::<Result<Event, Infallible>>();
+        let mut response = Sse::new(events)
+            .keep_alive(Duration::from_millis(10))
+            .into_response();
+
+        assert_eq!(collect_chunks(response.take_body(), 2), [":\n\n", ":\n\n"]);
+    }
+}
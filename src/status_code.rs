@@ -1,5 +1,5 @@
 #[repr(u16)]
-#[derive(Clone)]
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum StatusCode {
     Continue = 100,
     SwitchingProtocols = 101,
@@ -63,11 +63,85 @@ pub enum StatusCode {
 }
 
 impl StatusCode {
+    // Returns the status line that starts every response.
     pub fn into_bytes_response(self) -> Vec<u8> {
+        let reason = self.reason_phrase();
         let code: u16 = self.into();
-        let string = format!("HTTP/1.1 {} OK\r\n", code);
+        let string = format!("HTTP/1.1 {} {}\r\n", code, reason);
         string.into_bytes()
     }
+
+    // Informational, 204 and 304 responses can't carry a body, and aren't given a
+    // Content-Length header for it.
+    pub(crate) fn allows_body(&self) -> bool {
+        let code = *self as u16;
+        code >= 200 && code != 204 && code != 304
+    }
+
+    // Returns the reason phrase registered for the status code.
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            StatusCode::Continue => "Continue",
+            StatusCode::SwitchingProtocols => "Switching Protocols",
+            StatusCode::EarlyHints => "Early Hints",
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::Accepted => "Accepted",
+            StatusCode::NonAuthoritativeInformation => "Non-Authoritative Information",
+            StatusCode::NoContent => "No Content",
+            StatusCode::ResetContent => "Reset Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MultiStatus => "Multi-Status",
+            StatusCode::ImUsed => "IM Used",
+            StatusCode::MultipleChoice => "Multiple Choices",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::SeeOther => "See Other",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::TemporaryRedirect => "Temporary Redirect",
+            StatusCode::PermanentRedirect => "Permanent Redirect",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::PaymentRequired => "Payment Required",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::NotAcceptable => "Not Acceptable",
+            StatusCode::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::Gone => "Gone",
+            StatusCode::LengthRequired => "Length Required",
+            StatusCode::PreconditionFailed => "Precondition Failed",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UriTooLong => "URI Too Long",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RequestedRangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::ExpectationFailed => "Expectation Failed",
+            StatusCode::ImATeapot => "I'm a teapot",
+            StatusCode::MisdirectedRequest => "Misdirected Request",
+            StatusCode::UnprocessableEntity => "Unprocessable Entity",
+            StatusCode::Locked => "Locked",
+            StatusCode::FailedDependency => "Failed Dependency",
+            StatusCode::TooEarly => "Too Early",
+            StatusCode::UpgradeRequired => "Upgrade Required",
+            StatusCode::PreconditionRequired => "Precondition Required",
+            StatusCode::TooManyRequests => "Too Many Requests",
+            StatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            StatusCode::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::BadGateway => "Bad Gateway",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+            StatusCode::GatewayTimeout => "Gateway Timeout",
+            StatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+            StatusCode::VariantAlsoNegotiates => "Variant Also Negotiates",
+            StatusCode::InsufficientStorage => "Insufficient Storage",
+            StatusCode::LoopDetected => "Loop Detected",
+            StatusCode::NotExtended => "Not Extended",
+            StatusCode::NetworkAuthenticationRequired => "Network Authentication Required",
+        }
+    }
 }
 
 impl From<StatusCode> for u16 {
@@ -0,0 +1,354 @@
+use crate::extract::{FromRequest, Rejection};
+use crate::request::HttpRequest;
+use crate::response::{HttpResponse, IntoResponse};
+use crate::routing::endpoint::MethodType;
+use crate::status_code::StatusCode;
+use futures::future::{ready, BoxFuture, FutureExt};
+use std::fmt;
+use std::future::Future;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+// Client connection kept in the extensions of the requests asking for a protocol
+// upgrade, until the handler switches to the new protocol.
+pub(crate) struct Connection(BufReader<TcpStream>);
+
+impl Connection {
+    pub(crate) fn new(reader: BufReader<TcpStream>) -> Self {
+        Connection(reader)
+    }
+}
+
+// Whether the request asks to switch protocols or to open a tunnel with `CONNECT`.
+// Requests with a body aren't upgraded, as the body would have to be read before
+// the new protocol starts.
+pub(crate) fn wants_upgrade(request: &HttpRequest) -> bool {
+    let headers = request.headers();
+    let connection_upgrade = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .any(|option| option.trim().eq_ignore_ascii_case("upgrade"));
+    let has_body = headers.contains("Transfer-Encoding")
+        || headers
+            .get("Content-Length")
+            .is_some_and(|length| length.trim() != "0");
+    let upgrade = connection_upgrade && headers.contains("Upgrade");
+    (upgrade || request.method() == &MethodType::CONNECT) && !has_body
+}
+
+// Connection switched to another protocol after a `101 Switching Protocols`
+// response. Reads start with the bytes the client sent right after the request.
+pub struct Upgraded {
+    reader: BufReader<TcpStream>,
+}
+
+impl Upgraded {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.reader.get_ref().peer_addr()
+    }
+
+    // Bytes sent by the client after the request and already read from the
+    // connection, returned first by the reads.
+    pub fn buffered(&self) -> &[u8] {
+        self.reader.buffer()
+    }
+
+    // Opens another handle to the connection, e.g. to write while a thread reads.
+    pub fn try_clone_stream(&self) -> io::Result<TcpStream> {
+        self.reader.get_ref().try_clone()
+    }
+
+    // Returns the raw connection and the bytes already read from it.
+    pub fn into_parts(self) -> (TcpStream, Vec<u8>) {
+        let buffered = self.reader.buffer().to_vec();
+        (self.reader.into_inner(), buffered)
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reader.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.reader.get_mut().flush()
+    }
+}
+
+impl BufRead for Upgraded {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("peer_addr", &self.reader.get_ref().peer_addr().ok())
+            .finish()
+    }
+}
+
+type Callback = Box<dyn FnOnce(Upgraded) -> BoxFuture<'static, ()> + Send>;
+
+// Continuation of a `101 Switching Protocols` response, run with the connection
+// once the response is written.
+pub(crate) struct OnUpgrade {
+    connection: Upgraded,
+    callback: Callback,
+}
+
+impl OnUpgrade {
+    pub(crate) fn new<F, Fut>(connection: Connection, callback: F) -> Self
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        OnUpgrade {
+            connection: Upgraded {
+                reader: connection.0,
+            },
+            callback: Box::new(move |upgraded| callback(upgraded).boxed()),
+        }
+    }
+
+    pub(crate) async fn run(self) {
+        (self.callback)(self.connection).await
+    }
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnUpgrade")
+            .field("connection", &self.connection)
+            .finish()
+    }
+}
+
+// Extractor taking over the connection of the requests asking for a protocol upgrade
+// (with the `Upgrade` and `Connection: upgrade` headers) or for a tunnel (with the
+// `CONNECT` method). As `CONNECT` targets aren't paths, those requests are handled
+// by middleware rather than routed.
+pub struct Upgrade {
+    connection: Connection,
+    protocol: Option<String>,
+    connect: bool,
+}
+
+impl Upgrade {
+    // Returns the protocols asked by the client, `None` for `CONNECT` requests.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    // Whether the request asks for a tunnel with `CONNECT`, opened with `on_connect`.
+    pub fn is_connect(&self) -> bool {
+        self.connect
+    }
+
+    // Answers with `101 Switching Protocols`, switching to the given protocol, then
+    // runs the callback with the connection once the response is flushed. More
+    // headers can be added to the returned response, but it can't have a body.
+    // `CONNECT` requests don't switch protocols, they get 500 Internal Server Error.
+    pub fn on_upgrade<F, Fut>(self, protocol: &str, callback: F) -> HttpResponse
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        if self.connect {
+            return misused("CONNECT requests are answered with Upgrade::on_connect");
+        }
+        HttpResponse::new(StatusCode::SwitchingProtocols)
+            .with_header("Upgrade", protocol)
+            .with_header("Connection", "Upgrade")
+            .with_upgrade(OnUpgrade::new(self.connection, callback))
+    }
+
+    // Answers a `CONNECT` request with `200 OK`, then runs the callback with the
+    // tunnel once the response is flushed. Other requests get 500 Internal Server
+    // Error, as they switch protocols with `on_upgrade`.
+    pub fn on_connect<F, Fut>(self, callback: F) -> HttpResponse
+    where
+        F: FnOnce(Upgraded) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        if !self.connect {
+            return misused("protocol upgrades are answered with Upgrade::on_upgrade");
+        }
+        HttpResponse::new(StatusCode::Ok).with_upgrade(OnUpgrade::new(self.connection, callback))
+    }
+}
+
+// Answers the requests whose upgrade was taken with the wrong method.
+fn misused(message: &str) -> HttpResponse {
+    (StatusCode::InternalServerError, message.to_string()).into_response()
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgrade")
+            .field("protocol", &self.protocol)
+            .field("connect", &self.connect)
+            .finish()
+    }
+}
+
+impl FromRequest for Upgrade {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        let connect = request.method() == &MethodType::CONNECT;
+        let protocol = match connect {
+            true => None,
+            false => request.header("Upgrade").map(str::to_string),
+        };
+        let upgrade = match request.extensions_mut().remove::<Connection>() {
+            Some(connection) => Ok(Upgrade {
+                connection,
+                protocol,
+                connect,
+            }),
+            None => Err(Rejection::bad_request(
+                "the request doesn't ask for a protocol upgrade",
+            )),
+        };
+        ready(upgrade).boxed()
+    }
+}
+
+// Whether the response takes over the connection, which requires the matching status.
+pub(crate) fn accepts_upgrade(response: &HttpResponse, method: &MethodType) -> bool {
+    match method {
+        MethodType::CONNECT => (200..300).contains(&(response.status() as u16)),
+        _ => response.status() == StatusCode::SwitchingProtocols,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alcazar::AppBuilder;
+    use crate::extract::FromRequest;
+    use crate::middleware::Next;
+    use crate::request::HttpRequest;
+    use crate::response::{HttpResponse, IntoResponse};
+    use crate::router::Router;
+    use crate::routing::endpoint::MethodType;
+    use crate::upgrade::{Upgrade, Upgraded};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    // Sends back the messages of 5 bytes in uppercase
+    async fn shout(mut connection: Upgraded) {
+        let mut message = [0; 5];
+        while connection.read_exact(&mut message).is_ok() {
+            message.make_ascii_uppercase();
+            if connection.write_all(&message).is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn tunnel(connection: Upgraded) {
+        let (mut stream, buffered) = connection.into_parts();
+        let _ = stream.write_all(&buffered);
+        let _ = stream.write_all(b"tunnel");
+    }
+
+    async fn switch(upgrade: Upgrade) -> HttpResponse {
+        assert_eq!(upgrade.protocol(), Some("shout/1"));
+        upgrade.on_upgrade("shout/1", shout)
+    }
+
+    async fn misused_connect(upgrade: Upgrade) -> HttpResponse {
+        upgrade.on_connect(tunnel)
+    }
+
+    async fn connect(mut request: HttpRequest, next: Next) -> HttpResponse {
+        if request.method() != &MethodType::CONNECT {
+            return next.run(request).await;
+        }
+        match Upgrade::from_request(&mut request).await {
+            Ok(upgrade) => upgrade.on_connect(tunnel),
+            Err(rejection) => rejection.into_response(),
+        }
+    }
+
+    fn read_head(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+
+    #[test]
+    fn test_switch_protocols() {
+        let router = Router::new().with_endpoint("/shout", &["get"], switch);
+        let app = AppBuilder::default().set_router(router).start().unwrap();
+
+        let mut stream = TcpStream::connect(app.local_addr()).unwrap();
+        // The first message comes right after the request
+        stream
+            .write_all(
+                b"GET /shout HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: shout/1\r\n\r\nhello",
+            )
+            .unwrap();
+        assert_eq!(
+            read_head(&mut stream),
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: shout/1\r\nConnection: Upgrade\r\n\r\n"
+        );
+        stream.write_all(b"world").unwrap();
+        let mut messages = [0; 10];
+        stream.read_exact(&mut messages).unwrap();
+        assert_eq!(&messages, b"HELLOWORLD");
+    }
+
+    #[test]
+    fn test_reject_request_without_upgrade() {
+        let router = Router::new().with_endpoint("/shout", &["get"], switch);
+        let app = AppBuilder::default().set_router(router).start().unwrap();
+
+        let mut stream = TcpStream::connect(app.local_addr()).unwrap();
+        stream.write_all(b"GET /shout HTTP/1.1\r\n\r\n").unwrap();
+        assert!(read_head(&mut stream).starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn test_reject_tunnel_for_upgrade_request() {
+        let router = Router::new().with_endpoint("/shout", &["get"], misused_connect);
+        let app = AppBuilder::default().set_router(router).start().unwrap();
+
+        let mut stream = TcpStream::connect(app.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /shout HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: shout/1\r\n\r\n")
+            .unwrap();
+        assert!(read_head(&mut stream).starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    }
+
+    #[test]
+    fn test_connect_tunnel() {
+        let app = AppBuilder::default()
+            .with_middleware(connect)
+            .start()
+            .unwrap();
+
+        let mut stream = TcpStream::connect(app.local_addr()).unwrap();
+        stream
+            .write_all(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        // Responses to CONNECT have no body framing
+        assert_eq!(response, "HTTP/1.1 200 OK\r\n\r\ntunnel");
+    }
+}
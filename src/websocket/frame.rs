@@ -0,0 +1,281 @@
+use crate::error::WebSocketError;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use std::io::{self, ErrorKind, Read, Write};
+
+type Result<T> = std::result::Result<T, WebSocketError>;
+
+// Maximum payload of the control frames
+pub(crate) const MAX_CONTROL_PAYLOAD: usize = 125;
+
+// Trailer of the deflate blocks flushed at the end of each compressed message,
+// removed by the sender (RFC 7692, section 7.2.1)
+const DEFLATE_TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// Size by which the buffer of the decompressed messages grows
+const INFLATE_CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(OpCode::Continuation),
+            0x1 => Some(OpCode::Text),
+            0x2 => Some(OpCode::Binary),
+            0x8 => Some(OpCode::Close),
+            0x9 => Some(OpCode::Ping),
+            0xa => Some(OpCode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            OpCode::Continuation => 0x0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xa,
+        }
+    }
+
+    pub(crate) fn is_control(self) -> bool {
+        matches!(self, OpCode::Close | OpCode::Ping | OpCode::Pong)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Frame {
+    pub(crate) fin: bool,
+    // Set on the first frame of the compressed messages
+    pub(crate) rsv1: bool,
+    pub(crate) opcode: OpCode,
+    pub(crate) payload: Vec<u8>,
+}
+
+fn protocol_error(message: &str) -> WebSocketError {
+    WebSocketError::Protocol(message.to_string())
+}
+
+// Reads the next frame sent by the client, returning `None` when the connection is
+// closed before it starts. `buffered` is the size of the message received so far,
+// which can't grow over the `limit`.
+pub(crate) fn read_frame<R: Read>(
+    reader: &mut R,
+    buffered: usize,
+    limit: usize,
+) -> Result<Option<Frame>> {
+    let mut head = [0; 2];
+    loop {
+        match reader.read(&mut head[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    reader.read_exact(&mut head[1..])?;
+
+    let fin = head[0] & 0x80 != 0;
+    let rsv1 = head[0] & 0x40 != 0;
+    if head[0] & 0x30 != 0 {
+        return Err(protocol_error("reserved bits are set"));
+    }
+    let opcode =
+        OpCode::from_bits(head[0] & 0x0f).ok_or_else(|| protocol_error("unknown frame opcode"))?;
+    if head[1] & 0x80 == 0 {
+        return Err(protocol_error("frames sent by the client must be masked"));
+    }
+
+    let length = match head[1] & 0x7f {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+            u64::from(u16::from_be_bytes(length))
+        }
+        127 => {
+            let mut length = [0; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => u64::from(length),
+    };
+    if opcode.is_control() {
+        if !fin {
+            return Err(protocol_error("control frames can't be fragmented"));
+        }
+        if length > MAX_CONTROL_PAYLOAD as u64 {
+            return Err(protocol_error(
+                "control frame payload is larger than 125 bytes",
+            ));
+        }
+    } else if length > limit.saturating_sub(buffered) as u64 {
+        return Err(WebSocketError::MessageTooLarge(limit));
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+
+    Ok(Some(Frame {
+        fin,
+        rsv1,
+        opcode,
+        payload,
+    }))
+}
+
+// Writes a complete frame, unmasked as the server's frames are.
+pub(crate) fn write_frame<W: Write>(
+    writer: &mut W,
+    rsv1: bool,
+    opcode: OpCode,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut head = Vec::with_capacity(10);
+    head.push(0x80 | if rsv1 { 0x40 } else { 0 } | opcode.bits());
+    match payload.len() {
+        length if length < 126 => head.push(length as u8),
+        length if length <= u16::MAX as usize => {
+            head.push(126);
+            head.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            head.push(127);
+            head.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    writer.write_all(&head)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+// Compresses the payload of a message. The context isn't kept between the messages,
+// as negotiated with `server_no_context_takeover`.
+pub(crate) fn deflate(payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(payload)?;
+    // Flushing ends the data with an empty stored block, instead of the final block
+    encoder.flush()?;
+    let compressed = std::mem::take(encoder.get_mut());
+    match compressed.strip_suffix(&DEFLATE_TRAILER) {
+        Some(data) => Ok(data.to_vec()),
+        None => Ok(compressed),
+    }
+}
+
+// Decompresses the payload of a message, failing once it's larger than the limit.
+pub(crate) fn inflate(payload: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut input = payload.to_vec();
+    input.extend_from_slice(&DEFLATE_TRAILER);
+    let mut decompress = Decompress::new(false);
+    let mut data = Vec::new();
+    loop {
+        if data.len() > limit {
+            return Err(WebSocketError::MessageTooLarge(limit));
+        }
+        data.reserve(INFLATE_CHUNK_SIZE);
+        let (read, written) = (decompress.total_in(), data.len());
+        let status = decompress
+            .decompress_vec(&input[read as usize..], &mut data, FlushDecompress::Sync)
+            .map_err(|_| protocol_error("invalid compressed message"))?;
+        // The data is complete once the input is consumed without filling the output
+        let consumed = decompress.total_in() as usize == input.len();
+        if status == Status::StreamEnd || (consumed && data.len() < data.capacity()) {
+            break;
+        }
+        if decompress.total_in() == read && data.len() == written {
+            return Err(protocol_error("invalid compressed message"));
+        }
+    }
+    if data.len() > limit {
+        return Err(WebSocketError::MessageTooLarge(limit));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::WebSocketError;
+    use crate::websocket::frame::{deflate, inflate, read_frame, write_frame, Frame, OpCode};
+
+    // Frame as sent by a client, masked
+    fn client_frame(head: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![head, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    #[test]
+    fn test_read_masked_frame() {
+        let bytes = client_frame(0x81, b"hello");
+        let frame = read_frame(&mut &bytes[..], 0, 1024).unwrap();
+        assert_eq!(
+            frame,
+            Some(Frame {
+                fin: true,
+                rsv1: false,
+                opcode: OpCode::Text,
+                payload: b"hello".to_vec(),
+            })
+        );
+        assert!(read_frame(&mut &b""[..], 0, 1024).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_reject_invalid_frames() {
+        let unmasked = [0x81, 0x02, b'h', b'i'];
+        let fragmented_ping = client_frame(0x09, b"ping");
+        let reserved = client_frame(0xa1, b"hi");
+        for bytes in [&unmasked[..], &fragmented_ping, &reserved] {
+            let err = read_frame(&mut &bytes[..], 0, 1024).unwrap_err();
+            assert!(matches!(err, WebSocketError::Protocol(_)), "{:?}", err);
+        }
+
+        let bytes = client_frame(0x82, b"hello");
+        let err = read_frame(&mut &bytes[..], 1020, 1024).unwrap_err();
+        assert!(matches!(err, WebSocketError::MessageTooLarge(1024)));
+    }
+
+    #[test]
+    fn test_write_frame_lengths() {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, false, OpCode::Binary, &[0; 300]).unwrap();
+        assert_eq!(&bytes[..4], [0x82, 126, 0x01, 0x2c]);
+        assert_eq!(bytes.len(), 304);
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        // Larger than the chunks of the decompressed data
+        let message = "hello ".repeat(10_000);
+        let compressed = deflate(message.as_bytes()).unwrap();
+        assert!(compressed.len() < message.len());
+        assert!(!compressed.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+        assert_eq!(inflate(&compressed, 60_000).unwrap(), message.as_bytes());
+
+        let err = inflate(&compressed, 100).unwrap_err();
+        assert!(matches!(err, WebSocketError::MessageTooLarge(100)));
+    }
+}
@@ -0,0 +1,484 @@
+use crate::extract::{settings, FromRequest, Rejection};
+use crate::request::HttpRequest;
+use crate::response::HttpResponse;
+use crate::routing::endpoint::MethodType;
+use crate::status_code::StatusCode;
+use crate::upgrade::{Connection, OnUpgrade};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::future::{ready, BoxFuture, FutureExt};
+use sha1::{Digest, Sha1};
+use std::fmt;
+use std::future::Future;
+use tracing::error;
+
+mod frame;
+mod socket;
+
+pub use socket::{WebSocket, WebSocketReceiver, WebSocketSender};
+
+// Appended to the client's key to compute the `Sec-WebSocket-Accept` header
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Extension parameters of the accepted `permessage-deflate` offers. No compression
+// context is kept between the messages, in both directions.
+const DEFLATE_RESPONSE: &str =
+    "permessage-deflate; server_no_context_takeover; client_no_context_takeover";
+
+// Settings of the WebSocket connections, taken from the request extensions or the
+// application state like the `BodyLimit`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WebSocketConfig {
+    // Maximum size of a message received from the client, once decompressed
+    pub max_message_size: usize,
+    // Whether the `permessage-deflate` extension is accepted when the client offers it
+    pub deflate: bool,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        WebSocketConfig {
+            max_message_size: 16 * 1024 * 1024,
+            deflate: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Message::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Self {
+        Message::Binary(data)
+    }
+}
+
+// Status code and reason of a close message (RFC 6455, section 7.4).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub const NORMAL: u16 = 1000;
+    pub const GOING_AWAY: u16 = 1001;
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    pub const UNSUPPORTED_DATA: u16 = 1003;
+    pub const INVALID_DATA: u16 = 1007;
+    pub const POLICY_VIOLATION: u16 = 1008;
+    pub const MESSAGE_TOO_BIG: u16 = 1009;
+    pub const INTERNAL_ERROR: u16 = 1011;
+
+    pub fn new(code: u16, reason: &str) -> Self {
+        CloseFrame {
+            code,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+// Extractor accepting the upgrade of the connection to the WebSocket protocol, after
+// checking the opening handshake of the client. The connection is handed over by
+// the response of `on_upgrade`, once it's sent.
+pub struct WebSocketUpgrade {
+    connection: Connection,
+    key: String,
+    config: WebSocketConfig,
+    deflate_offered: bool,
+    offered_protocols: Vec<String>,
+    protocol: Option<String>,
+}
+
+impl WebSocketUpgrade {
+    pub fn config(mut self, config: WebSocketConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    // Selects the first of the given subprotocols offered by the client, sent back
+    // in the `Sec-WebSocket-Protocol` header.
+    pub fn protocols(mut self, protocols: &[&str]) -> Self {
+        self.protocol = protocols
+            .iter()
+            .find(|protocol| {
+                self.offered_protocols
+                    .iter()
+                    .any(|offered| offered == *protocol)
+            })
+            .map(|protocol| protocol.to_string());
+        self
+    }
+
+    pub fn selected_protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    // Answers with `101 Switching Protocols`, then runs the callback with the
+    // WebSocket once the response is sent.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> HttpResponse
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let deflate = self.config.deflate && self.deflate_offered;
+        let mut response = HttpResponse::new(StatusCode::SwitchingProtocols)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", &accept_key(&self.key));
+        if deflate {
+            response = response.with_header("Sec-WebSocket-Extensions", DEFLATE_RESPONSE);
+        }
+        if let Some(protocol) = &self.protocol {
+            response = response.with_header("Sec-WebSocket-Protocol", protocol);
+        }
+
+        let config = self.config;
+        response.with_upgrade(OnUpgrade::new(
+            self.connection,
+            move |upgraded| async move {
+                match WebSocket::new(upgraded, config, deflate) {
+                    Ok(socket) => callback(socket).await,
+                    Err(err) => error!("failed to set up the WebSocket connection: {}", err),
+                }
+            },
+        ))
+    }
+}
+
+impl fmt::Debug for WebSocketUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketUpgrade")
+            .field("config", &self.config)
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+fn header_tokens<'a>(request: &'a HttpRequest, name: &'a str) -> impl Iterator<Item = &'a str> {
+    request
+        .headers()
+        .get_all(name)
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+// Whether one of the offers of the `Sec-WebSocket-Extensions` header is a
+// `permessage-deflate` one with parameters compatible with `DEFLATE_RESPONSE`.
+fn offers_deflate(request: &HttpRequest) -> bool {
+    header_tokens(request, "Sec-WebSocket-Extensions").any(|offer| {
+        let mut params = offer.split(';').map(str::trim);
+        params.next() == Some("permessage-deflate")
+            && params.all(|param| {
+                let name = param.split('=').next().unwrap_or("").trim();
+                matches!(
+                    name,
+                    "server_no_context_takeover"
+                        | "client_no_context_takeover"
+                        | "client_max_window_bits"
+                )
+            })
+    })
+}
+
+fn upgrade_required(message: &str) -> Rejection {
+    let response = HttpResponse::new(StatusCode::UpgradeRequired)
+        .with_header("Upgrade", "websocket")
+        .with_header("Sec-WebSocket-Version", "13")
+        .with_header("Content-Type", "text/plain; charset=utf-8")
+        .with_body(message);
+    Rejection::new(StatusCode::UpgradeRequired, message).with_response(response)
+}
+
+fn handshake(request: &mut HttpRequest) -> Result<WebSocketUpgrade, Rejection> {
+    if request.method() != &MethodType::GET {
+        return Err(Rejection::bad_request(
+            "WebSocket handshakes must use the GET method",
+        ));
+    }
+    let upgrade = header_tokens(request, "Upgrade")
+        .any(|protocol| protocol.eq_ignore_ascii_case("websocket"));
+    if !upgrade {
+        return Err(upgrade_required(
+            "expected an upgrade to the WebSocket protocol",
+        ));
+    }
+    if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        return Err(upgrade_required(
+            "only the version 13 of the WebSocket protocol is supported",
+        ));
+    }
+    let key = match request.header("Sec-WebSocket-Key").map(str::trim) {
+        Some(key) if STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16) => key.to_string(),
+        _ => {
+            return Err(Rejection::bad_request(
+                "missing or invalid Sec-WebSocket-Key header",
+            ))
+        }
+    };
+    let deflate_offered = offers_deflate(request);
+    let offered_protocols = header_tokens(request, "Sec-WebSocket-Protocol")
+        .map(str::to_string)
+        .collect();
+    let config = settings::<WebSocketConfig>(request).unwrap_or_default();
+    let connection = request
+        .extensions_mut()
+        .remove::<Connection>()
+        .ok_or_else(|| Rejection::bad_request("the connection can't be upgraded"))?;
+
+    Ok(WebSocketUpgrade {
+        connection,
+        key,
+        config,
+        deflate_offered,
+        offered_protocols,
+        protocol: None,
+    })
+}
+
+impl FromRequest for WebSocketUpgrade {
+    fn from_request(request: &mut HttpRequest) -> BoxFuture<'_, Result<Self, Rejection>> {
+        ready(handshake(request)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alcazar::AppBuilder;
+    use crate::router::Router;
+    use crate::websocket::frame::inflate;
+    use crate::websocket::{accept_key, Message, WebSocket, WebSocketConfig};
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use futures::future::join;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    async fn echo(mut socket: WebSocket) {
+        while let Some(Ok(message)) = socket.recv().await {
+            if let Message::Text(_) | Message::Binary(_) = message {
+                if socket.send(message).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Greets the client while waiting for its first message, then answers it
+    async fn greet(socket: WebSocket) {
+        let (mut sender, mut receiver) = socket.split();
+        let greeting = async move {
+            sender.send(Message::from("hello")).await.unwrap();
+            sender
+        };
+        let (message, mut sender) = join(receiver.recv(), greeting).await;
+        if let Some(Ok(Message::Text(text))) = message {
+            let _ = sender.send(Message::from(format!("got {}", text))).await;
+        }
+    }
+
+    fn client_frame(head: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![head, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    // Connects to the echo endpoint, returning the reader positioned after the
+    // response head, and the head
+    fn connect(config: WebSocketConfig, extensions: &str) -> (BufReader<TcpStream>, String) {
+        connect_to(
+            Router::new().with_websocket("/ws", echo),
+            config,
+            extensions,
+        )
+    }
+
+    fn connect_to(
+        router: Router,
+        config: WebSocketConfig,
+        extensions: &str,
+    ) -> (BufReader<TcpStream>, String) {
+        let app = AppBuilder::default()
+            .set_router(router)
+            .with_state(config)
+            .start()
+            .unwrap();
+        let mut stream = TcpStream::connect(app.local_addr()).unwrap();
+        write!(
+            stream,
+            "GET /ws HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+            extensions
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        (reader, head)
+    }
+
+    fn read_server_frame(reader: &mut impl Read) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head).unwrap();
+        assert_eq!(head[1] & 0x80, 0, "server frames aren't masked");
+        let mut payload = vec![0; (head[1] & 0x7f) as usize];
+        reader.read_exact(&mut payload).unwrap();
+        (head[0], payload)
+    }
+
+    #[test]
+    fn test_accept_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_echo_fragmented_message() {
+        let (mut reader, head) = connect(WebSocketConfig::default(), "");
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(!head.contains("Sec-WebSocket-Extensions"));
+
+        let stream = reader.get_mut();
+        stream.write_all(&client_frame(0x01, b"Hel")).unwrap();
+        // Control frames can come between the fragments
+        stream.write_all(&client_frame(0x89, b"ping")).unwrap();
+        stream.write_all(&client_frame(0x80, b"lo")).unwrap();
+        assert_eq!(read_server_frame(&mut reader), (0x8a, b"ping".to_vec()));
+        assert_eq!(read_server_frame(&mut reader), (0x81, b"Hello".to_vec()));
+
+        reader
+            .get_mut()
+            .write_all(&client_frame(0x88, &[0x03, 0xe8]))
+            .unwrap();
+        assert_eq!(read_server_frame(&mut reader), (0x88, vec![0x03, 0xe8]));
+    }
+
+    #[test]
+    fn test_send_while_receiving() {
+        let router = Router::new().with_websocket("/ws", greet);
+        let (mut reader, _) = connect_to(router, WebSocketConfig::default(), "");
+        // The greeting is sent while the handler waits for the client's message
+        reader
+            .get_mut()
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(read_server_frame(&mut reader), (0x81, b"hello".to_vec()));
+
+        reader
+            .get_mut()
+            .write_all(&client_frame(0x81, b"world"))
+            .unwrap();
+        assert_eq!(
+            read_server_frame(&mut reader),
+            (0x81, b"got world".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_close_on_protocol_errors() {
+        let config = WebSocketConfig {
+            max_message_size: 8,
+            ..WebSocketConfig::default()
+        };
+        let (mut reader, _) = connect(config, "");
+        reader
+            .get_mut()
+            .write_all(&client_frame(0x82, b"too large"))
+            .unwrap();
+        assert_eq!(read_server_frame(&mut reader), (0x88, vec![0x03, 0xf1]));
+
+        let (mut reader, _) = connect(config, "");
+        reader
+            .get_mut()
+            .write_all(&[0x81, 0x02, b'h', b'i'])
+            .unwrap();
+        assert_eq!(read_server_frame(&mut reader), (0x88, vec![0x03, 0xea]));
+
+        let (mut reader, _) = connect(config, "");
+        reader
+            .get_mut()
+            .write_all(&client_frame(0x81, &[0xff, 0xfe]))
+            .unwrap();
+        assert_eq!(read_server_frame(&mut reader), (0x88, vec![0x03, 0xef]));
+    }
+
+    #[test]
+    fn test_permessage_deflate() {
+        let config = WebSocketConfig {
+            deflate: true,
+            ..WebSocketConfig::default()
+        };
+        let offer = "Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n";
+        let (mut reader, head) = connect(config, offer);
+        assert!(head.contains(
+            "Sec-WebSocket-Extensions: permessage-deflate; server_no_context_takeover; client_no_context_takeover\r\n"
+        ));
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"compressed").unwrap();
+        encoder.flush().unwrap();
+        let compressed = encoder.get_ref().clone();
+        let payload = &compressed[..compressed.len() - 4];
+        reader
+            .get_mut()
+            .write_all(&client_frame(0xc1, payload))
+            .unwrap();
+
+        let (head, payload) = read_server_frame(&mut reader);
+        assert_eq!(head, 0xc1);
+        assert_eq!(inflate(&payload, 1024).unwrap(), b"compressed");
+    }
+
+    #[test]
+    fn test_reject_invalid_handshake() {
+        let router = Router::new().with_websocket("/ws", echo);
+        let app = AppBuilder::default().set_router(router).start().unwrap();
+        let mut stream = TcpStream::connect(app.local_addr()).unwrap();
+        stream
+            .write_all(b"GET /ws HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(response.contains("Sec-WebSocket-Version: 13\r\n"));
+    }
+}
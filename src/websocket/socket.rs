@@ -0,0 +1,398 @@
+use crate::error::WebSocketError;
+use crate::upgrade::Upgraded;
+use crate::websocket::frame::{self, OpCode, MAX_CONTROL_PAYLOAD};
+use crate::websocket::{CloseFrame, Message, WebSocketConfig};
+use bastion_executor::run::run;
+use futures::channel::mpsc;
+use futures::sink::{Sink, SinkExt};
+use futures::stream::{Stream, StreamExt};
+use lightproc::prelude::ProcStack;
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpStream};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+use std::thread;
+
+type Result<T> = std::result::Result<T, WebSocketError>;
+
+// Data message being received in fragments
+struct Partial {
+    opcode: OpCode,
+    compressed: bool,
+    payload: Vec<u8>,
+}
+
+// WebSocket connection with a client, as a duplex stream of messages. Messages are
+// read by a thread of their own, so waiting for the next one leaves the executor
+// free, and `split` gives halves that can be used by separate tasks.
+//
+// Pings are answered right away and close messages are echoed, before being
+// returned to the handler. Protocol errors close the connection with the matching
+// status code and end the stream after returning the error.
+pub struct WebSocket {
+    sender: WebSocketSender,
+    receiver: WebSocketReceiver,
+    config: WebSocketConfig,
+    deflate: bool,
+}
+
+impl WebSocket {
+    pub(crate) fn new(
+        connection: Upgraded,
+        config: WebSocketConfig,
+        deflate: bool,
+    ) -> io::Result<Self> {
+        let writer = Arc::new(Mutex::new(Writer {
+            stream: connection.try_clone_stream()?,
+            deflate,
+            close_sent: false,
+        }));
+        let stream = connection.try_clone_stream()?;
+        // A message is read ahead at most, the reader then waits for the handler
+        let (messages_sender, messages) = mpsc::channel(0);
+        let reader = Reader {
+            connection,
+            writer: writer.clone(),
+            limit: config.max_message_size,
+            deflate,
+            partial: None,
+        };
+        thread::spawn(move || reader.run(messages_sender));
+
+        Ok(WebSocket {
+            sender: WebSocketSender { writer },
+            receiver: WebSocketReceiver { messages, stream },
+            config,
+            deflate,
+        })
+    }
+
+    pub fn config(&self) -> &WebSocketConfig {
+        &self.config
+    }
+
+    // Whether the `permessage-deflate` extension was negotiated.
+    pub fn is_compressed(&self) -> bool {
+        self.deflate
+    }
+
+    // Returns the next message, or `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<Result<Message>> {
+        self.receiver.recv().await
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<()> {
+        self.sender.send(message).await
+    }
+
+    // Starts the closing handshake, the client's answer is then returned by `recv`.
+    pub async fn close(&mut self, frame: Option<CloseFrame>) -> Result<()> {
+        self.sender.close(frame).await
+    }
+
+    // Splits the connection into the halves sending and receiving the messages,
+    // e.g. to send from a task while another one waits for the client.
+    pub fn split(self) -> (WebSocketSender, WebSocketReceiver) {
+        (self.sender, self.receiver)
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.get_mut().receiver).poll_next(cx)
+    }
+}
+
+impl Sink<Message> for WebSocket {
+    type Error = WebSocketError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().sender).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<()> {
+        Pin::new(&mut self.get_mut().sender).start_send(message)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().sender).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().sender).poll_close(cx)
+    }
+}
+
+// Half of a WebSocket sending the messages. The writes go straight to the
+// connection, and block the executor thread like the response bodies.
+pub struct WebSocketSender {
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl WebSocketSender {
+    pub async fn send(&mut self, message: Message) -> Result<()> {
+        lock(&self.writer).write_message(message)
+    }
+
+    // Starts the closing handshake, the client's answer is then returned by the
+    // receiving half.
+    pub async fn close(&mut self, frame: Option<CloseFrame>) -> Result<()> {
+        lock(&self.writer).write_message(Message::Close(frame))
+    }
+}
+
+impl Sink<Message> for WebSocketSender {
+    type Error = WebSocketError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: Message) -> Result<()> {
+        lock(&self.writer).write_message(message)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(lock(&self.writer).stream.flush().map_err(Into::into))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut writer = lock(&self.writer);
+        if writer.close_sent {
+            return Poll::Ready(Ok(()));
+        }
+        Poll::Ready(writer.write_message(Message::Close(None)))
+    }
+}
+
+// Half of a WebSocket receiving the messages, as they are read by the thread of
+// the connection. Dropping it stops the thread.
+pub struct WebSocketReceiver {
+    messages: mpsc::Receiver<Result<Message>>,
+    stream: TcpStream,
+}
+
+impl WebSocketReceiver {
+    // Returns the next message, or `None` once the connection is closed.
+    pub async fn recv(&mut self) -> Option<Result<Message>> {
+        self.messages.next().await
+    }
+}
+
+impl Stream for WebSocketReceiver {
+    type Item = Result<Message>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.get_mut().messages).poll_next(cx)
+    }
+}
+
+impl Drop for WebSocketReceiver {
+    fn drop(&mut self) {
+        // Ends the blocking read of the thread, the connection stays open for writes
+        let _ = self.stream.shutdown(Shutdown::Read);
+    }
+}
+
+fn lock(writer: &Mutex<Writer>) -> MutexGuard<'_, Writer> {
+    writer.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Writing side of the connection, shared by the sending half and the reading
+// thread, which answers pings and close messages.
+struct Writer {
+    stream: TcpStream,
+    deflate: bool,
+    // Whether a close message was sent to the client
+    close_sent: bool,
+}
+
+impl Writer {
+    fn write_message(&mut self, message: Message) -> Result<()> {
+        if self.close_sent {
+            return Err(WebSocketError::ConnectionClosed);
+        }
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OpCode::Text, text.into_bytes()),
+            Message::Binary(data) => (OpCode::Binary, data),
+            Message::Ping(data) => (OpCode::Ping, data),
+            Message::Pong(data) => (OpCode::Pong, data),
+            Message::Close(frame) => {
+                self.close_sent = true;
+                let payload = frame.map_or_else(Vec::new, |frame| {
+                    let mut payload = frame.code.to_be_bytes().to_vec();
+                    payload.extend_from_slice(frame.reason.as_bytes());
+                    payload
+                });
+                (OpCode::Close, payload)
+            }
+        };
+        if opcode.is_control() && payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(WebSocketError::Protocol(
+                "control frame payload is larger than 125 bytes".to_string(),
+            ));
+        }
+
+        if self.deflate && !opcode.is_control() {
+            let payload = frame::deflate(&payload)?;
+            frame::write_frame(&mut self.stream, true, opcode, &payload)?;
+        } else {
+            frame::write_frame(&mut self.stream, false, opcode, &payload)?;
+        }
+        Ok(())
+    }
+}
+
+// Reading side of the connection, run by a thread of its own which hands the
+// messages over to the receiving half.
+struct Reader {
+    connection: Upgraded,
+    writer: Arc<Mutex<Writer>>,
+    limit: usize,
+    deflate: bool,
+    partial: Option<Partial>,
+}
+
+impl Reader {
+    fn run(mut self, mut messages: mpsc::Sender<Result<Message>>) {
+        loop {
+            let message = match self.next_message() {
+                Some(message) => message,
+                None => return,
+            };
+            // The stream ends after a close message or an error, or once the
+            // receiving half is dropped
+            let done = matches!(message, Ok(Message::Close(_)) | Err(_));
+            if run(messages.send(message), ProcStack::default()).is_err() || done {
+                return;
+            }
+        }
+    }
+
+    fn next_message(&mut self) -> Option<Result<Message>> {
+        match self.read_message() {
+            Ok(message) => message.map(Ok),
+            Err(err) => {
+                let code = match err {
+                    WebSocketError::Protocol(_) => Some(CloseFrame::PROTOCOL_ERROR),
+                    WebSocketError::InvalidUtf8 => Some(CloseFrame::INVALID_DATA),
+                    WebSocketError::MessageTooLarge(_) => Some(CloseFrame::MESSAGE_TOO_BIG),
+                    _ => None,
+                };
+                let mut writer = lock(&self.writer);
+                if let Some(code) = code.filter(|_| !writer.close_sent) {
+                    let _ = writer.write_message(Message::Close(Some(CloseFrame::new(code, ""))));
+                }
+                Some(Err(err))
+            }
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Option<Message>> {
+        let limit = self.limit;
+        loop {
+            let buffered = self
+                .partial
+                .as_ref()
+                .map_or(0, |partial| partial.payload.len());
+            let frame = match frame::read_frame(&mut self.connection, buffered, limit)? {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            if frame.rsv1 && (!self.deflate || frame.opcode.is_control()) {
+                return Err(WebSocketError::Protocol(
+                    "unexpected compressed frame".to_string(),
+                ));
+            }
+
+            let partial = match (frame.opcode, self.partial.take()) {
+                (OpCode::Ping, partial) => {
+                    self.partial = partial;
+                    let mut writer = lock(&self.writer);
+                    if !writer.close_sent {
+                        frame::write_frame(
+                            &mut writer.stream,
+                            false,
+                            OpCode::Pong,
+                            &frame.payload,
+                        )?;
+                    }
+                    return Ok(Some(Message::Ping(frame.payload)));
+                }
+                (OpCode::Pong, partial) => {
+                    self.partial = partial;
+                    return Ok(Some(Message::Pong(frame.payload)));
+                }
+                (OpCode::Close, _) => {
+                    let close = parse_close(&frame.payload)?;
+                    let mut writer = lock(&self.writer);
+                    if !writer.close_sent {
+                        writer.write_message(Message::Close(close.clone()))?;
+                    }
+                    return Ok(Some(Message::Close(close)));
+                }
+                (OpCode::Continuation, Some(mut partial)) if !frame.rsv1 => {
+                    partial.payload.extend_from_slice(&frame.payload);
+                    partial
+                }
+                (OpCode::Text, None) | (OpCode::Binary, None) => Partial {
+                    opcode: frame.opcode,
+                    compressed: frame.rsv1,
+                    payload: frame.payload,
+                },
+                (OpCode::Continuation, _) => {
+                    return Err(WebSocketError::Protocol(
+                        "unexpected continuation frame".to_string(),
+                    ))
+                }
+                (_, Some(_)) => {
+                    return Err(WebSocketError::Protocol(
+                        "new message started before the end of the previous one".to_string(),
+                    ))
+                }
+            };
+            if !frame.fin {
+                self.partial = Some(partial);
+                continue;
+            }
+
+            let payload = match partial.compressed {
+                true => frame::inflate(&partial.payload, limit)?,
+                false => partial.payload,
+            };
+            return match partial.opcode {
+                OpCode::Text => String::from_utf8(payload)
+                    .map(|text| Some(Message::Text(text)))
+                    .map_err(|_| WebSocketError::InvalidUtf8),
+                _ => Ok(Some(Message::Binary(payload))),
+            };
+        }
+    }
+}
+
+// Parses the status code and the reason of a close message.
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(WebSocketError::Protocol(
+            "close payload without status code".to_string(),
+        )),
+        [high, low, reason @ ..] => {
+            let code = u16::from_be_bytes([*high, *low]);
+            // Codes that can be sent by the endpoints (RFC 6455, section 7.4)
+            let valid = matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999);
+            if !valid {
+                return Err(WebSocketError::Protocol(format!(
+                    "invalid close code {}",
+                    code
+                )));
+            }
+            let reason =
+                String::from_utf8(reason.to_vec()).map_err(|_| WebSocketError::InvalidUtf8)?;
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}